@@ -0,0 +1,172 @@
+//! Throughput of the main stages of compression, over generated text and random bytes, and the
+//! time to build trees over a large alphabet.
+//!
+//! Run with `cargo bench`, adding `--features parallel` to include the parallel counter. Each
+//! stage is timed over several runs and the fastest is reported.
+//!
+//! This is a plain `harness = false` binary rather than criterion, so that the crate keeps no
+//! dependencies, even for development. It gives no statistics beyond the fastest run, so compare
+//! figures from the same machine.
+
+extern crate rust_huffman;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::hint::black_box;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use rust_huffman::{BitOrder, BitReader, BitWriter, DecodeTable, TieBreak, Tree, build_tree, count_frequencies, decode, decompress, encode, encoding_table, write_compressed};
+
+const SIZE: usize = 1 << 22;
+const RUNS: usize = 10;
+/// How many symbols to build trees over, far more than bytes can have.
+const ALPHABET: u32 = 1 << 16;
+
+const WORDS: &[&str] = &[
+    "the", "of", "and", "to", "in", "a", "is", "that", "for", "it", "as", "was", "with", "be",
+    "by", "on", "not", "he", "this", "are", "or", "his", "from", "at", "which", "but", "have",
+    "an", "had", "they", "you", "were", "their", "one", "all", "we", "can", "her", "has",
+    "there", "been", "if", "more", "when", "will", "would", "who", "so", "no", "huffman",
+];
+
+fn main() {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+
+    let mut text = Vec::with_capacity(SIZE + 16);
+    while text.len() < SIZE {
+        text.extend_from_slice(WORDS[next() as usize % WORDS.len()].as_bytes());
+        text.push(if next() % 12 == 0 { b'\n' } else { b' ' });
+    }
+    text.truncate(SIZE);
+    let random: Vec<u8> = (0..SIZE).map(|_| next() as u8).collect();
+
+    for (name, data) in &[("text", &text), ("random", &random)] {
+        bench(name, data);
+    }
+
+    let weights: HashMap<u32, u64> = (0..ALPHABET).map(|c| (c, next() % 1_000_000)).collect();
+    bench_alphabet(&weights);
+}
+
+/// Building trees over a large alphabet, from a heap and from already sorted weights.
+fn bench_alphabet(weights: &HashMap<u32, u64>) {
+    let mut sorted: Vec<_> = weights.iter().map(|(&c, &count)| (c, count)).collect();
+    sorted.sort_by_key(|&(c, count)| (count, c));
+
+    report("alphabet", "from_with_tiebreak", 0, time(|| {
+        black_box(Tree::from_with_tiebreak(weights.clone(), TieBreak::ByInsertion));
+    }));
+    report("alphabet", "Tree::from", 0, time(|| {
+        black_box(Tree::from(weights.clone()));
+    }));
+    report("alphabet", "Tree::from_sorted", 0, time(|| {
+        black_box(Tree::from_sorted(sorted.clone()));
+    }));
+}
+
+fn bench(name: &str, data: &[u8]) {
+    let freqs = count_frequencies(data).unwrap();
+    let tree = build_tree(freqs.clone()).unwrap();
+    let table = encoding_table(&tree);
+
+    report(name, "count_frequencies", data.len(), time(|| {
+        black_box(count_frequencies(data).unwrap());
+    }));
+    // How `count_frequencies` used to count, a byte at a time through `Read::bytes`, to compare.
+    report(name, "count bytes()", data.len(), time(|| {
+        let mut map = HashMap::new();
+        for c in data.bytes() {
+            *map.entry(c.unwrap()).or_insert(0u64) += 1;
+        }
+        black_box(map);
+    }));
+    #[cfg(feature = "parallel")]
+    report(name, "count_parallel", data.len(), time(|| {
+        black_box(rust_huffman::count_frequencies_parallel(data));
+    }));
+    // Building the tree only depends on the number of symbols, so there's no throughput to give.
+    report(name, "Tree::from", 0, time(|| {
+        black_box(Tree::from(freqs.clone()));
+    }));
+    report(name, "encode", data.len(), time(|| {
+        let mut output = Vec::with_capacity(data.len());
+        let mut writer = BitWriter::new(&mut output);
+        encode(data, &table, &mut writer).unwrap();
+        black_box(writer.finish().unwrap());
+    }));
+    // How `encode` used to look up each byte, in the `HashMap` from `encoding_table`, to compare
+    // with the array it indexes now.
+    report(name, "encode HashMap", data.len(), time(|| {
+        let mut output = Vec::with_capacity(data.len());
+        let mut writer = BitWriter::new(&mut output);
+        for c in data {
+            let (code, depth) = table[c];
+            writer.write_code(code, depth).unwrap();
+        }
+        black_box(writer.finish().unwrap());
+    }));
+    // Straight to an unbuffered file, so that every write the `BitWriter` makes is a system call.
+    let path = env::temp_dir().join("rust-huffman-throughput");
+    report(name, "encode to file", data.len(), time(|| {
+        let mut writer = BitWriter::new(File::create(&path).unwrap());
+        encode(data, &table, &mut writer).unwrap();
+        black_box(writer.finish().unwrap());
+    }));
+    let _ = fs::remove_file(&path);
+    report(name, "write_compressed", data.len(), time(|| {
+        let mut output = Vec::with_capacity(data.len());
+        write_compressed(data, &mut output).unwrap();
+        black_box(output);
+    }));
+
+    let mut coded = Vec::new();
+    let mut writer = BitWriter::new(&mut coded);
+    encode(data, &table, &mut writer).unwrap();
+    writer.finish().unwrap();
+    // Walking the tree a bit at a time, against looking up a byte of bits at a time.
+    report(name, "decode", data.len(), time(|| {
+        let mut output = Vec::with_capacity(data.len());
+        decode(&mut BitReader::new(&coded[..]), &tree, data.len() as u64, &mut output).unwrap();
+        black_box(output);
+    }));
+    report(name, "DecodeTable", data.len(), time(|| {
+        let mut output = Vec::with_capacity(data.len());
+        let table = DecodeTable::new(&tree, BitOrder::LsbFirst);
+        table.decode(&mut BitReader::new(&coded[..]), data.len() as u64, &mut output).unwrap();
+        black_box(output);
+    }));
+    let mut compressed = Vec::new();
+    write_compressed(data, &mut compressed).unwrap();
+    report(name, "decompress", data.len(), time(|| {
+        black_box(decompress(&compressed).unwrap());
+    }));
+}
+
+/// The fastest of `RUNS` runs of `f`.
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(corpus: &str, stage: &str, bytes: usize, elapsed: Duration) {
+    if bytes == 0 {
+        println!("{:8} {:20} {:>10.3?}", corpus, stage, elapsed);
+    } else {
+        let rate = bytes as f64 / (1 << 20) as f64 / elapsed.as_secs_f64();
+        println!("{:8} {:20} {:>10.3?} {:>10.1} MB/s", corpus, stage, elapsed, rate);
+    }
+}
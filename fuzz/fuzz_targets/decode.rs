@@ -0,0 +1,18 @@
+//! Decode arbitrary bytes, which must end in a result, never a panic or a hang.
+//!
+//! Run with `cargo fuzz run decode fuzz/corpus/decode fuzz/seeds/decode` from the repository root.
+//! The seeds are streams at the decoder's limits, such as a tree that never ends, which overflowed
+//! the stack before trees were capped at 256 leaves.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rust_huffman;
+
+use std::io::sink;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rust_huffman::read_blocks(data, sink());
+    let _ = rust_huffman::read_codebook(data);
+});
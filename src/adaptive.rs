@@ -0,0 +1,360 @@
+use std::io::{Read, Write, self};
+
+use bits::{BitReader, BitWriter};
+use error::HuffmanError;
+
+/// Encode bytes with a tree that adapts as it goes (the FGK algorithm), so that no frequency
+/// count or table has to be sent ahead of the data. The first occurrence of each byte is sent
+/// as the code of the "not yet transmitted" leaf followed by the raw byte.
+///
+/// The same code followed by a byte that has already been sent can't otherwise occur, so `flush`
+/// sends it to mark padding out to a whole byte. `finish`, or dropping the encoder, ends the
+/// stream the same way, so a stream always ends on a byte boundary just after its last code or
+/// after a marker and its padding. `AdaptiveDecoder::read_byte` returns `None` on reaching it.
+pub struct AdaptiveEncoder<W: Write> {
+    model: Model,
+    /// Only `None` once `finish` has taken it.
+    writer: Option<BitWriter<W>>,
+}
+
+impl<W: Write> AdaptiveEncoder<W> {
+    pub fn new(inner: W) -> AdaptiveEncoder<W> {
+        AdaptiveEncoder { model: Model::new(), writer: Some(BitWriter::new(inner)) }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) -> Result<(), io::Error> {
+        let leaf = self.model.leaves[byte as usize];
+        let path = self.model.path(leaf.unwrap_or(self.model.nyt));
+        let writer = self.writer.as_mut().expect("AdaptiveEncoder already finished");
+        for bit in path {
+            writer.write_bits(bit as u64, 1)?;
+        }
+        if leaf.is_none() {
+            writer.write_bits(u64::from(byte), 8)?;
+        }
+
+        self.model.update(byte);
+        Ok(())
+    }
+
+    /// Send every byte written so far on to the inner writer and flush it, so that a decoder
+    /// reading it can decode them all without waiting for more. This costs at most a few bytes,
+    /// for a marker and the padding after it.
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        self.mark_padding()?;
+        self.writer.as_mut().expect("AdaptiveEncoder already finished").flush()?;
+        Ok(())
+    }
+
+    /// End the stream as `flush` does and return the inner writer. Unlike dropping the encoder,
+    /// this reports any error from the final writes.
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        self.mark_padding()?;
+        self.writer.take().expect("AdaptiveEncoder already finished").finish()
+    }
+
+    /// Send the marker that tells a decoder to skip to the next byte, unless the bits written so
+    /// far already end on a byte boundary.
+    fn mark_padding(&mut self) -> Result<(), io::Error> {
+        let writer = self.writer.as_mut().expect("AdaptiveEncoder already finished");
+        if writer.padding_bits() > 0 {
+            let seen = self.model.leaves.iter()
+                .position(Option::is_some)
+                .expect("Bits were written, so a byte has been sent");
+            for bit in self.model.path(self.model.nyt) {
+                writer.write_bits(bit as u64, 1)?;
+            }
+            writer.write_bits(seen as u64, 8)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Ends the stream as `finish` does, if it wasn't called. As with `BitWriter`, any error is
+/// ignored, so call `finish` to find out whether the writes succeeded.
+impl<W: Write> Drop for AdaptiveEncoder<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.mark_padding();
+        }
+    }
+}
+
+/// Decode bytes written by an `AdaptiveEncoder`, rebuilding the same tree as they are read.
+pub struct AdaptiveDecoder<R: Read> {
+    model: Model,
+    reader: BitReader<R>,
+}
+
+impl<R: Read> AdaptiveDecoder<R> {
+    pub fn new(inner: R) -> AdaptiveDecoder<R> {
+        AdaptiveDecoder { model: Model::new(), reader: BitReader::new(inner) }
+    }
+
+    /// Decode the next byte, or `None` if the stream ends cleanly, on a byte boundary before
+    /// another code starts. A stream that ends partway through a code is `UnexpectedEof`.
+    pub fn read_byte(&mut self) -> Result<Option<u8>, HuffmanError> {
+        loop {
+            let mut node = Model::ROOT;
+            let mut started = false;
+            while let Some(children) = self.model.nodes[node].children {
+                let bit = match self.reader.read_bit()? {
+                    Some(bit) => bit,
+                    None if !started => return Ok(None),
+                    None => return Err(HuffmanError::UnexpectedEof),
+                };
+                started = true;
+                node = if bit { children.1 } else { children.0 };
+            }
+
+            let byte = match self.model.nodes[node].symbol {
+                Some(byte) => byte,
+                None => {
+                    let byte = match self.reader.read_bit()? {
+                        // Nothing has been sent yet, so the lone NYT leaf takes no bits.
+                        None if !started => return Ok(None),
+                        Some(first) => first as u64 | self.reader.read_bits(7)? << 1,
+                        None => return Err(HuffmanError::UnexpectedEof),
+                    } as u8;
+                    if self.model.leaves[byte as usize].is_some() {
+                        // The marker from `AdaptiveEncoder::flush`, with padding after it.
+                        self.reader.skip_to_byte();
+                        continue;
+                    }
+                    byte
+                }
+            };
+
+            self.model.update(byte);
+            return Ok(Some(byte));
+        }
+    }
+}
+
+/// The shared tree. Nodes live in `nodes` and never move; `order` lists them from the root down
+/// by their FGK number, and every update keeps weights non-increasing along it (the sibling
+/// property) by swapping a node with the first node of equal weight before incrementing it.
+struct Model {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+    leaves: [Option<usize>; 256],
+    nyt: usize,
+}
+
+struct Node {
+    weight: u64,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>,
+    symbol: Option<u8>,
+    rank: usize,
+}
+
+impl Model {
+    const ROOT: usize = 0;
+
+    fn new() -> Model {
+        let nyt = Node { weight: 0, parent: None, children: None, symbol: None, rank: 0 };
+        Model { nodes: vec![nyt], order: vec![Self::ROOT], leaves: [None; 256], nyt: Self::ROOT }
+    }
+
+    /// The branches from the root down to `node`.
+    fn path(&self, mut node: usize) -> Vec<bool> {
+        let mut path = Vec::new();
+        while let Some(parent) = self.nodes[node].parent {
+            let (_, right) = self.nodes[parent].children.expect("Parent has children");
+            path.push(right == node);
+            node = parent;
+        }
+
+        path.reverse();
+        path
+    }
+
+    fn update(&mut self, byte: u8) {
+        let mut node = match self.leaves[byte as usize] {
+            Some(leaf) => leaf,
+            None => self.split_nyt(byte),
+        };
+
+        loop {
+            let leader = self.leader(node);
+            if Some(leader) != self.nodes[node].parent {
+                self.swap(node, leader);
+            }
+
+            self.nodes[node].weight += 1;
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+
+    /// Give the NYT leaf two children: a new NYT leaf and a leaf for `byte`, which is returned.
+    fn split_nyt(&mut self, byte: u8) -> usize {
+        let parent = self.nyt;
+        let leaf = self.push(Node { weight: 0, parent: Some(parent), children: None, symbol: Some(byte), rank: 0 });
+        let nyt = self.push(Node { weight: 0, parent: Some(parent), children: None, symbol: None, rank: 0 });
+
+        self.nodes[parent].children = Some((nyt, leaf));
+        self.leaves[byte as usize] = Some(leaf);
+        self.nyt = nyt;
+        leaf
+    }
+
+    fn push(&mut self, mut node: Node) -> usize {
+        let id = self.nodes.len();
+        node.rank = self.order.len();
+        self.nodes.push(node);
+        self.order.push(id);
+        id
+    }
+
+    /// The highest numbered node with the same weight as `node`.
+    fn leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let rank = self.order[..self.nodes[node].rank]
+            .iter()
+            .rposition(|&other| self.nodes[other].weight != weight)
+            .map_or(0, |rank| rank + 1);
+        self.order[rank]
+    }
+
+    /// Exchange the positions of two subtrees, neither of which contains the other.
+    fn swap(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+
+        let parent_a = self.nodes[a].parent.expect("Only the root has no parent");
+        let parent_b = self.nodes[b].parent.expect("Only the root has no parent");
+        if parent_a == parent_b {
+            let (left, right) = self.nodes[parent_a].children.expect("Parent has children");
+            self.nodes[parent_a].children = Some((right, left));
+        } else {
+            self.replace_child(parent_a, a, b);
+            self.replace_child(parent_b, b, a);
+            self.nodes[a].parent = Some(parent_b);
+            self.nodes[b].parent = Some(parent_a);
+        }
+
+        let (rank_a, rank_b) = (self.nodes[a].rank, self.nodes[b].rank);
+        self.order.swap(rank_a, rank_b);
+        self.nodes[a].rank = rank_b;
+        self.nodes[b].rank = rank_a;
+    }
+
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let (left, right) = self.nodes[parent].children.expect("Parent has children");
+        self.nodes[parent].children = Some(if left == old { (new, right) } else { (left, new) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn encoded(input: &[u8]) -> Vec<u8> {
+        let mut encoder = AdaptiveEncoder::new(Vec::new());
+        for &c in input {
+            encoder.write_byte(c).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    fn decoded(data: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+        let mut decoder = AdaptiveDecoder::new(data);
+        let mut output = Vec::new();
+        while let Some(c) = decoder.read_byte()? {
+            output.push(c);
+        }
+        Ok(output)
+    }
+
+    #[test]
+    fn a_shifting_distribution_round_trips() {
+        let mut input = b"aaaabaaacaaab".repeat(40);
+        input.extend(b"xyzzyxyzzzzy".repeat(40));
+        let data = encoded(&input);
+        assert!(data.len() < input.len() / 2);
+        assert_eq!(decoded(&data).unwrap(), input);
+    }
+
+    #[test]
+    fn flushing_midway_still_round_trips() {
+        let mut encoder = AdaptiveEncoder::new(Vec::new());
+        for &c in b"abc" {
+            encoder.write_byte(c).unwrap();
+        }
+        encoder.flush().unwrap();
+        for &c in b"cab" {
+            encoder.write_byte(c).unwrap();
+        }
+        let data = encoder.finish().unwrap();
+
+        // The decoder skips the padding after the marker and carries on.
+        assert_eq!(decoded(&data).unwrap(), b"abccab");
+    }
+
+    #[test]
+    fn dropping_the_encoder_ends_the_stream_as_finish_does() {
+        let mut dropped = Vec::new();
+        {
+            let mut encoder = AdaptiveEncoder::new(&mut dropped);
+            for &c in b"abcab" {
+                encoder.write_byte(c).unwrap();
+            }
+        }
+        assert_eq!(dropped, encoded(b"abcab"));
+    }
+
+    #[test]
+    fn a_stream_cut_mid_code_is_an_error() {
+        let data = encoded(b"hello, hello");
+        match decoded(&data[..data.len() - 1]) {
+            Err(HuffmanError::UnexpectedEof) => {}
+            other => panic!("Expected UnexpectedEof, got {:?}", other),
+        }
+    }
+
+    /// A writer whose output can be looked at while it is still being written to.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn what_is_flushed_decodes_before_anything_more_is_written() {
+        let sent = Shared::default();
+        let mut encoder = AdaptiveEncoder::new(sent.clone());
+        for &c in b"hello" {
+            encoder.write_byte(c).unwrap();
+        }
+        encoder.flush().unwrap();
+
+        let so_far = sent.0.borrow().clone();
+        let mut decoder = AdaptiveDecoder::new(&so_far[..]);
+        for &c in b"hello" {
+            assert_eq!(decoder.read_byte().unwrap(), Some(c));
+        }
+
+        for &c in b" world" {
+            encoder.write_byte(c).unwrap();
+        }
+        encoder.finish().unwrap();
+        assert!(sent.0.borrow().starts_with(&so_far));
+        assert_eq!(decoded(&sent.0.borrow()).unwrap(), b"hello world");
+    }
+}
@@ -0,0 +1,794 @@
+use std::io::{Read, Write, self};
+
+use stream_order;
+
+/// The order in which bits are packed into each byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitOrder {
+    /// Bits fill each byte from the least significant end, and multi-bit values are written
+    /// starting from their least significant bit.
+    #[default]
+    LsbFirst,
+    /// Bits fill each byte from the most significant end, and multi-bit values are written
+    /// starting from their most significant bit.
+    MsbFirst,
+}
+
+/// Write individual bits to a file. Least significant bits first, unless another order is given.
+///
+/// Whole bytes are collected and written to the inner writer `CHUNK` at a time, so it needn't be
+/// buffered itself.
+pub struct BitWriter<W: Write> {
+    buffer: u8,
+    buffer_len: usize,
+    /// Whole bytes waiting to be written to `inner`.
+    pending: Vec<u8>,
+    order: BitOrder,
+    /// Only `None` once `finish` has taken it.
+    inner: Option<W>,
+}
+
+impl<W: Write> BitWriter<W> {
+    const BYTE_BITS: usize = 8;
+
+    /// How many whole bytes are collected before they are written to the inner writer.
+    pub const CHUNK: usize = 1 << 12;
+
+    pub fn new(inner: W) -> BitWriter<W> {
+        BitWriter::with_order(inner, BitOrder::LsbFirst)
+    }
+
+    pub fn with_order(inner: W, order: BitOrder) -> BitWriter<W> {
+        BitWriter {
+            buffer: 0u8,
+            buffer_len: 0usize,
+            pending: Vec::with_capacity(Self::CHUNK),
+            order,
+            inner: Some(inner),
+        }
+    }
+
+    pub fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    /// Write the low `length` bits of `bits`, where `length` is at most 64. Any higher bits are
+    /// ignored, and a `length` of 0 writes nothing.
+    pub fn write_bits(&mut self, bits: u64, length: usize) -> Result<(), io::Error> {
+        debug_assert!(length <= 64, "Can't write {} bits at once", length);
+        let mut pair = (low_bits(bits, length), length);
+        while pair.1 > 0usize {
+            pair = self.consume_bits(pair);
+            self.flush_byte()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a code from `Tree::encode`, which holds the branch taken at the root in its most
+    /// significant bit, so that its bits come out root first whatever the order. In `MsbFirst`
+    /// order this is the same as `write_bits`, but in `LsbFirst` the code is reversed first.
+    pub fn write_code(&mut self, code: u64, depth: usize) -> Result<(), io::Error> {
+        let bits = stream_order(code, depth, self.order);
+        self.write_bits(bits, depth)
+    }
+
+    fn flush_byte(&mut self) -> Result <(), io::Error> {
+        if self.buffer_len == Self::BYTE_BITS {
+            self.pending.push(self.buffer);
+            self.buffer = 0;
+            self.buffer_len = 0;
+            if self.pending.len() == Self::CHUNK {
+                let inner = self.inner.as_mut().expect("BitWriter already finished");
+                inner.write_all(&self.pending)?;
+                self.pending.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pad the bits written so far out to a whole byte with zeros, then write everything waiting
+    /// and flush the inner writer, so that whatever reads it has every bit written so far.
+    /// Returns how many bits of padding were added, which a reader has to skip over.
+    pub fn flush(&mut self) -> Result<usize, io::Error> {
+        let padding = self.padding_bits();
+        if self.buffer_len > 0 {
+            let byte = self.padded_byte();
+            self.pending.push(byte);
+            self.buffer = 0;
+            self.buffer_len = 0;
+        }
+
+        let inner = self.inner.as_mut().expect("BitWriter already finished");
+        inner.write_all(&self.pending)?;
+        self.pending.clear();
+        inner.flush()?;
+        Ok(padding)
+    }
+
+    /// How many zero bits `finish` would pad the final byte with, given the bits written so far.
+    pub fn padding_bits(&self) -> usize {
+        (Self::BYTE_BITS - self.buffer_len) % Self::BYTE_BITS
+    }
+
+    /// Write out any bytes still waiting and the final partial byte, padded with zeros, and return
+    /// the inner writer. Unlike dropping the writer, this reports any error from those writes.
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        let mut inner = self.inner.take().expect("BitWriter already finished");
+        if self.buffer_len > 0 {
+            let byte = self.padded_byte();
+            self.pending.push(byte);
+        }
+        inner.write_all(&self.pending)?;
+
+        Ok(inner)
+    }
+
+    /// Another name for `finish`, after `BufWriter::into_inner`, for writing more after the bits.
+    pub fn into_inner(self) -> Result<W, io::Error> {
+        self.finish()
+    }
+
+    /// The partial byte in the buffer with every bit not yet written set to zero, so that the
+    /// same bits always end a stream with the same byte.
+    fn padded_byte(&self) -> u8 {
+        match self.order {
+            BitOrder::LsbFirst => low_bits(u64::from(self.buffer), self.buffer_len) as u8,
+            BitOrder::MsbFirst => self.buffer & !(0xffu8.checked_shr(self.buffer_len as u32).unwrap_or(0)),
+        }
+    }
+
+    /// Move as many bits as fit into the buffer, placing them after the bits already held.
+    fn consume_bits(&mut self, (bits, length): (u64, usize)) -> (u64, usize) {
+        let to_consume = Self::BYTE_BITS.saturating_sub(self.buffer_len).min(length);
+        let remaining = length - to_consume;
+        let rest = match self.order {
+            BitOrder::LsbFirst => {
+                self.buffer |= (low_bits(bits, to_consume) as u8) << self.buffer_len;
+                bits.overflowing_shr(to_consume as u32).0
+            }
+            BitOrder::MsbFirst => {
+                let chunk = low_bits(bits >> remaining, to_consume) as u8;
+                self.buffer |= chunk << (Self::BYTE_BITS - self.buffer_len - to_consume);
+                low_bits(bits, remaining)
+            }
+        };
+        self.buffer_len += to_consume;
+        (rest, remaining)
+    }
+}
+
+/// Writes out any bytes still waiting and the final partial byte, padded with zeros, if `finish`
+/// wasn't called. As with `BufWriter`, any error is ignored, so call `finish` to find out whether
+/// the writes succeeded.
+impl<W: Write> Drop for BitWriter<W> {
+    fn drop(&mut self) {
+        if self.buffer_len > 0 {
+            let byte = self.padded_byte();
+            self.pending.push(byte);
+        }
+        if let Some(ref mut inner) = self.inner {
+            let _ = inner.write_all(&self.pending);
+        }
+    }
+}
+
+/// Read individual bits from a file. Least significant bits first, unless another order is given.
+///
+/// Bytes are only taken from the inner reader as their bits are needed, except by `peek_bits`,
+/// which may read as far as `MAX_PEEK` bits ahead, and `prefetch`.
+pub struct BitReader<R: Read> {
+    /// Bits read from `inner` but not yet produced, the next lowest for `LsbFirst` and highest
+    /// for `MsbFirst`.
+    buffer: u64,
+    buffer_len: usize,
+    order: BitOrder,
+    inner: R,
+}
+
+impl<R: Read> BitReader<R> {
+    const BYTE_BITS: usize = 8;
+
+    /// The most bits that can be looked at ahead of time with `peek_bits`.
+    pub const MAX_PEEK: usize = 56;
+
+    pub fn new(inner: R) -> BitReader<R> {
+        BitReader::with_order(inner, BitOrder::LsbFirst)
+    }
+
+    pub fn with_order(inner: R, order: BitOrder) -> BitReader<R> {
+        BitReader { buffer: 0u64, buffer_len: 0usize, order, inner }
+    }
+
+    pub fn order(&self) -> BitOrder {
+        self.order
+    }
+
+    /// Read a single bit, or `None` if the stream ended cleanly on a byte boundary.
+    pub fn read_bit(&mut self) -> Result<Option<bool>, io::Error> {
+        self.fill(1)?;
+        if self.buffer_len == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(self.produce_bits(1) == 1))
+    }
+
+    pub fn read_bits(&mut self, length: usize) -> Result<u64, io::Error> {
+        let mut bits = 0u64;
+        let mut read = 0usize;
+        while read < length {
+            let to_read = (length - read).min(32);
+            self.fill(to_read)?;
+            if self.buffer_len < to_read {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Bit stream ended early"));
+            }
+
+            let chunk = self.produce_bits(to_read);
+            bits = match self.order {
+                BitOrder::LsbFirst => bits | chunk << read,
+                BitOrder::MsbFirst => bits << to_read | chunk,
+            };
+            read += to_read;
+        }
+
+        Ok(bits)
+    }
+
+    /// Look at up to the next `length` bits without reading them, returning them as `read_bits`
+    /// would along with how many there were. Fewer are returned only at the end of the stream.
+    pub fn peek_bits(&mut self, length: usize) -> Result<(u64, usize), io::Error> {
+        assert!(length <= Self::MAX_PEEK, "Can only peek at {} bits", Self::MAX_PEEK);
+        if self.buffer_len < length {
+            // Top up as far as possible, so that most peeks can be answered from the buffer.
+            self.fill(Self::MAX_PEEK)?;
+        }
+
+        let available = length.min(self.buffer_len);
+        let bits = match self.order {
+            BitOrder::LsbFirst => low_bits(self.buffer, available),
+            BitOrder::MsbFirst => self.buffer.checked_shr((64 - available) as u32).unwrap_or(0),
+        };
+        Ok((bits, available))
+    }
+
+    /// Like `peek_bits`, but only looks at bits that are already buffered, returning `None` if
+    /// fewer than `length` are.
+    #[inline]
+    pub fn peek_buffered(&self, length: usize) -> Option<u64> {
+        if self.buffer_len < length {
+            return None;
+        }
+
+        Some(match self.order {
+            BitOrder::LsbFirst => low_bits(self.buffer, length),
+            BitOrder::MsbFirst => self.buffer.checked_shr((64 - length) as u32).unwrap_or(0),
+        })
+    }
+
+    /// How many bits have been read from the inner reader but not yet produced.
+    #[inline]
+    pub fn buffered_bits(&self) -> usize {
+        self.buffer_len
+    }
+
+    /// Read bytes until at least `length` bits, up to `MAX_PEEK`, are buffered, or the stream
+    /// ends. Unlike `peek_bits`, this reads no further than asked, for a caller that knows how
+    /// much more of the stream there is and mustn't read past it.
+    pub fn prefetch(&mut self, length: usize) -> Result<(), io::Error> {
+        assert!(length <= Self::MAX_PEEK, "Can only prefetch {} bits", Self::MAX_PEEK);
+        self.fill(length)
+    }
+
+    /// Skip over `length` bits that have already been seen with `peek_bits`.
+    #[inline]
+    pub fn consume_bits(&mut self, length: usize) {
+        assert!(length <= self.buffer_len, "Can only consume bits that have been peeked");
+        self.produce_bits(length);
+    }
+
+    /// Skip the rest of the byte that the next bit would come from, such as the padding from
+    /// `BitWriter::flush`. Nothing is skipped at a byte boundary.
+    pub fn skip_to_byte(&mut self) {
+        let partial = self.buffer_len % Self::BYTE_BITS;
+        self.produce_bits(partial);
+    }
+
+    /// Give back the inner reader, such as to read what follows the bit stream. Reading is lazy,
+    /// so after reading up to the end of a byte nothing is lost, but the rest of a partly read
+    /// byte, and anything buffered by `peek_bits`, is dropped.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read bytes until at least `length` bits are buffered, or the stream ends. Reads interrupted
+    /// by a signal are retried.
+    fn fill(&mut self, length: usize) -> Result<(), io::Error> {
+        while self.buffer_len < length {
+            let mut byte = [0u8];
+            match self.inner.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+
+            let byte = u64::from(byte[0]);
+            self.buffer |= match self.order {
+                BitOrder::LsbFirst => byte << self.buffer_len,
+                BitOrder::MsbFirst => byte << (64 - Self::BYTE_BITS - self.buffer_len),
+            };
+            self.buffer_len += Self::BYTE_BITS;
+        }
+
+        Ok(())
+    }
+
+    /// Take the next `length` of the buffered bits.
+    #[inline]
+    fn produce_bits(&mut self, length: usize) -> u64 {
+        let bits = match self.order {
+            BitOrder::LsbFirst => {
+                let bits = low_bits(self.buffer, length);
+                self.buffer = self.buffer.checked_shr(length as u32).unwrap_or(0);
+                bits
+            }
+            BitOrder::MsbFirst => {
+                let bits = self.buffer.checked_shr((64 - length) as u32).unwrap_or(0);
+                self.buffer = self.buffer.checked_shl(length as u32).unwrap_or(0);
+                bits
+            }
+        };
+        self.buffer_len -= length;
+        bits
+    }
+}
+
+/// Write bytes as text instead: the eight bits of each as `0`s and `1`s, in the order they are
+/// read in `order`, with a space between bytes. Put under a `BitWriter` with the same order, it
+/// shows the bits in the order they were written.
+pub struct BitText<W: Write> {
+    inner: W,
+    order: BitOrder,
+    started: bool,
+}
+
+impl<W: Write> BitText<W> {
+    pub fn new(inner: W, order: BitOrder) -> BitText<W> {
+        BitText { inner, order, started: false }
+    }
+}
+
+impl<W: Write> Write for BitText<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let mut text = Vec::with_capacity(buf.len() * 9);
+        for &byte in buf {
+            if self.started {
+                text.push(b' ');
+            }
+            self.started = true;
+            for i in 0..8 {
+                let shift = match self.order {
+                    BitOrder::LsbFirst => i,
+                    BitOrder::MsbFirst => 7 - i,
+                };
+                text.push(if (byte >> shift) & 1 == 1 { b'1' } else { b'0' });
+            }
+        }
+
+        self.inner.write_all(&text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
+
+/// The lowest `length` bits of `bits`.
+fn low_bits(bits: u64, length: usize) -> u64 {
+    if length >= 64 {
+        bits
+    } else {
+        bits & ((1u64 << length) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use decode;
+    use testing::Xorshift;
+    use tree::Tree::{self, Leaf};
+
+    #[test]
+    fn write_bits_across_byte_boundaries() {
+        let mut output = Vec::new();
+        let mut writer = BitWriter::new(&mut output);
+        writer.write_bits(0b101, 3).unwrap();
+        writer.write_bits(0b110_0110, 7).unwrap();
+        writer.write_bits(0b11_1000_0111, 10).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(output, [0x35, 0x1f, 0x0e]);
+    }
+
+    #[test]
+    fn whole_bytes_are_written_as_they_fill() {
+        let mut output = Vec::new();
+        let mut writer = BitWriter::new(&mut output);
+        for _ in 0..3 {
+            writer.write_bits(0xa5, 8).unwrap();
+        }
+        assert_eq!(writer.padding_bits(), 0);
+        writer.finish().unwrap();
+
+        assert_eq!(output, [0xa5; 3]);
+    }
+
+    /// Write random runs of bits in `order` and check they read back the same.
+    fn assert_random_bits_round_trip(order: BitOrder, seed: u64) {
+        let mut random = Xorshift::new(seed);
+        for _ in 0..20 {
+            let writes: Vec<(u64, usize)> = (0..random.next_u64() % 200)
+                .map(|_| (random.next_u64(), (random.next_u64() % 65) as usize))
+                .collect();
+
+            let mut output = Vec::new();
+            let mut writer = BitWriter::with_order(&mut output, order);
+            for &(bits, length) in &writes {
+                writer.write_bits(bits, length).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let mut reader = BitReader::with_order(&output[..], order);
+            for &(bits, length) in &writes {
+                assert_eq!(reader.read_bits(length).unwrap(), low_bits(bits, length));
+            }
+        }
+    }
+
+    #[test]
+    fn random_bits_read_back_as_written() {
+        assert_random_bits_round_trip(BitOrder::LsbFirst, 2);
+    }
+
+    #[test]
+    fn random_msb_first_bits_read_back_as_written() {
+        assert_random_bits_round_trip(BitOrder::MsbFirst, 14);
+    }
+
+    #[test]
+    fn the_orders_lay_out_bytes_differently() {
+        let layout = |order| {
+            let mut writer = BitWriter::with_order(Vec::new(), order);
+            writer.write_bits(0b1, 1).unwrap();
+            writer.write_bits(0b0011, 4).unwrap();
+            writer.write_bits(0b1_1110_0000, 9).unwrap();
+            writer.finish().unwrap()
+        };
+
+        assert_eq!(layout(BitOrder::LsbFirst), [0b0000_0111, 0b0011_1100]);
+        assert_eq!(layout(BitOrder::MsbFirst), [0b1001_1111, 0b1000_0000]);
+    }
+
+    #[test]
+    fn read_bit_is_none_at_a_clean_end() {
+        let mut reader = BitReader::new(&[0b1000_0001u8][..]);
+        assert_eq!(reader.read_bit().unwrap(), Some(true));
+        assert_eq!(reader.read_bits(6).unwrap(), 0);
+        assert_eq!(reader.read_bit().unwrap(), Some(true));
+        assert_eq!(reader.read_bit().unwrap(), None);
+        assert_eq!(reader.read_bits(1).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// A writer whose reader has gone away.
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "Broken pipe"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finish_reports_a_failed_write() {
+        let mut writer = BitWriter::new(BrokenPipe);
+        writer.write_bits(0b101, 3).unwrap();
+        assert_eq!(writer.finish().err().unwrap().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn dropping_ignores_a_failed_write() {
+        let mut writer = BitWriter::new(BrokenPipe);
+        writer.write_bits(0b101, 3).unwrap();
+        drop(writer);
+    }
+
+    #[test]
+    fn finish_leaves_nothing_for_drop_to_write() {
+        let mut output = Vec::new();
+        {
+            let mut writer = BitWriter::new(&mut output);
+            writer.write_bits(0b101, 3).unwrap();
+            let inner = writer.finish().unwrap();
+            inner.push(0xff);
+        }
+        assert_eq!(output, [0b101, 0xff]);
+    }
+
+    #[test]
+    fn padding_tops_up_the_last_byte() {
+        for total in 0..40 {
+            let mut writer = BitWriter::new(Vec::new());
+            for _ in 0..total {
+                writer.write_bits(1, 1).unwrap();
+            }
+            assert_eq!(writer.padding_bits(), (8 - total % 8) % 8);
+            assert_eq!(writer.flush().unwrap(), (8 - total % 8) % 8);
+            assert_eq!(writer.padding_bits(), 0);
+        }
+    }
+
+    #[test]
+    fn the_last_byte_is_padded_with_zeros() {
+        for total in 1..8 {
+            for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+                let ones = (1u8 << total) - 1;
+                let expected = match order {
+                    BitOrder::LsbFirst => ones,
+                    BitOrder::MsbFirst => ones << (8 - total),
+                };
+
+                let mut writer = BitWriter::with_order(Vec::new(), order);
+                writer.write_bits(u64::MAX, total).unwrap();
+                assert_eq!(writer.finish().unwrap(), [expected]);
+
+                let mut dropped = Vec::new();
+                BitWriter::with_order(&mut dropped, order).write_bits(u64::MAX, total).unwrap();
+                assert_eq!(dropped, [expected]);
+            }
+        }
+    }
+
+    #[test]
+    fn codes_come_out_root_first_in_either_order() {
+        // Three symbols, so that one code has two bits whose order matters.
+        let tree: Tree = Leaf(b'a', 2) + (Leaf(b'b', 1) + Leaf(b'c', 1));
+        let codes = tree.encode();
+        assert_eq!(codes[&b'b'], (0b10, 2));
+
+        for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let mut writer = BitWriter::with_order(Vec::new(), order);
+            for c in b"abcab" {
+                let (code, depth) = codes[c];
+                writer.write_code(code, depth).unwrap();
+            }
+            let data = writer.finish().unwrap();
+
+            let mut reader = BitReader::with_order(&data[..], order);
+            assert_eq!(reader.read_bit().unwrap(), Some(false));
+            assert_eq!(reader.read_bit().unwrap(), Some(true));
+            assert_eq!(reader.read_bit().unwrap(), Some(false));
+
+            let mut output = Vec::new();
+            decode(&mut BitReader::with_order(&data[..], order), &tree, 5, &mut output).unwrap();
+            assert_eq!(output, b"abcab");
+        }
+    }
+
+    /// Accepts one byte per call to `write`.
+    struct OneAtATime(Vec<u8>);
+
+    impl Write for OneAtATime {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.extend(buf.first());
+            Ok(buf.len().min(1))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Accepts nothing, without an error.
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_writes_are_retried() {
+        let bytes = Xorshift::new(43).bytes(3 * BitWriter::<Vec<u8>>::CHUNK, 256);
+        let mut writer = BitWriter::new(OneAtATime(Vec::new()));
+        for &c in &bytes {
+            writer.write_bits(u64::from(c), 8).unwrap();
+        }
+        writer.write_bits(1, 1).unwrap();
+        let OneAtATime(output) = writer.finish().unwrap();
+
+        assert_eq!(output[..bytes.len()], bytes[..]);
+        assert_eq!(output[bytes.len()..], [1]);
+    }
+
+    #[test]
+    fn writing_nothing_is_an_error() {
+        let mut writer = BitWriter::new(Full);
+        writer.write_bits(1, 1).unwrap();
+        assert_eq!(writer.finish().err().unwrap().kind(), io::ErrorKind::WriteZero);
+
+        let mut writer = BitWriter::new(Full);
+        let result = (0..BitWriter::<Full>::CHUNK).try_for_each(|_| writer.write_bits(0xff, 8));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn writing_zero_bits_writes_nothing() {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(u64::MAX, 0).unwrap();
+        assert_eq!(writer.padding_bits(), 0);
+        assert!(writer.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn all_64_bits_can_be_written_at_once() {
+        for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let mut writer = BitWriter::with_order(Vec::new(), order);
+            writer.write_bits(1, 3).unwrap();
+            writer.write_bits(0x0123_4567_89ab_cdef, 64).unwrap();
+            let data = writer.finish().unwrap();
+            assert_eq!(data.len(), 9);
+
+            let mut reader = BitReader::with_order(&data[..], order);
+            assert_eq!(reader.read_bits(3).unwrap(), 1);
+            assert_eq!(reader.read_bits(64).unwrap(), 0x0123_4567_89ab_cdef);
+        }
+    }
+
+    #[test]
+    fn bits_above_the_length_are_ignored() {
+        for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let write = |bits| {
+                let mut writer = BitWriter::with_order(Vec::new(), order);
+                writer.write_bits(bits, 5).unwrap();
+                writer.write_bits(0, 3).unwrap();
+                writer.finish().unwrap()
+            };
+            assert_eq!(write(0b1111_1111_1111_0110), write(0b1_0110));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Can't write 65 bits at once")]
+    fn more_than_64_bits_is_a_bug() {
+        let _ = BitWriter::new(Vec::new()).write_bits(0, 65);
+    }
+
+    /// Counts the calls to `write` as well as keeping what they write.
+    #[derive(Default)]
+    struct CountingWrites {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for CountingWrites {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bits_come_out_the_same_however_they_are_split() {
+        // Enough for several chunks.
+        let words: Vec<u64> = {
+            let mut random = Xorshift::new(73);
+            (0..1000 + 3 * BitWriter::<Vec<u8>>::CHUNK / 8).map(|_| random.next_u64()).collect()
+        };
+        for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let mut bulk = BitWriter::with_order(CountingWrites::default(), order);
+            for &word in &words {
+                bulk.write_bits(word, 64).unwrap();
+            }
+            let bulk = bulk.finish().unwrap();
+            assert_eq!(bulk.data.len(), words.len() * 8);
+            assert!(bulk.writes <= bulk.data.len() / BitWriter::<Vec<u8>>::CHUNK + 1, "{} writes", bulk.writes);
+
+            // The same bits a bit at a time, and in uneven pieces.
+            let mut bitwise = BitWriter::with_order(Vec::new(), order);
+            let mut pieces = BitWriter::with_order(Vec::new(), order);
+            let mut random = Xorshift::new(37);
+            for &word in &words {
+                let bits: Vec<u64> = match order {
+                    BitOrder::LsbFirst => (0..64).map(|i| word >> i & 1).collect(),
+                    BitOrder::MsbFirst => (0..64).rev().map(|i| word >> i & 1).collect(),
+                };
+                for &bit in &bits {
+                    bitwise.write_bits(bit, 1).unwrap();
+                }
+
+                let split = (random.next_u64() % 65) as usize;
+                let (first, second) = bits.split_at(split);
+                for piece in &[first, second] {
+                    let value = match order {
+                        BitOrder::LsbFirst => piece.iter().rev().fold(0, |value, &bit| value << 1 | bit),
+                        BitOrder::MsbFirst => piece.iter().fold(0, |value, &bit| value << 1 | bit),
+                    };
+                    pieces.write_bits(value, piece.len()).unwrap();
+                }
+            }
+            assert_eq!(bitwise.finish().unwrap(), bulk.data);
+            assert_eq!(pieces.finish().unwrap(), bulk.data);
+        }
+    }
+
+    #[test]
+    fn a_trailer_follows_the_bits_after_into_inner() {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(0b1_0110, 5).unwrap();
+        let mut output = writer.into_inner().unwrap();
+        output.write_all(b"end").unwrap();
+        assert_eq!(output, [0b1_0110, b'e', b'n', b'd']);
+
+        let mut reader = BitReader::new(&output[..]);
+        assert_eq!(reader.read_bits(5).unwrap(), 0b1_0110);
+        reader.skip_to_byte();
+        let mut trailer = Vec::new();
+        reader.into_inner().read_to_end(&mut trailer).unwrap();
+        assert_eq!(trailer, b"end");
+    }
+
+    #[test]
+    fn flushed_bits_read_back_before_the_writer_is_done() {
+        for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let mut writer = BitWriter::with_order(Vec::new(), order);
+            writer.write_bits(0b101, 3).unwrap();
+            assert_eq!(writer.flush().unwrap(), 5);
+            writer.write_bits(0b11, 2).unwrap();
+            assert_eq!(writer.flush().unwrap(), 6);
+            writer.write_bits(0xab, 8).unwrap();
+            assert_eq!(writer.flush().unwrap(), 0);
+
+            let output = writer.finish().unwrap();
+            assert_eq!(output.len(), 3);
+            let mut reader = BitReader::with_order(&output[..], order);
+            assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+            reader.skip_to_byte();
+            assert_eq!(reader.read_bits(2).unwrap(), 0b11);
+            reader.skip_to_byte();
+            assert_eq!(reader.read_bits(8).unwrap(), 0xab);
+            assert_eq!(reader.read_bit().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn bit_text_shows_bits_in_the_order_written() {
+        for &order in &[BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let mut text = Vec::new();
+            let mut writer = BitWriter::with_order(BitText::new(&mut text, order), order);
+            for &bit in &[1, 1, 0, 1, 0, 0, 0, 1, 1] {
+                writer.write_bits(bit, 1).unwrap();
+            }
+            writer.finish().unwrap();
+            assert_eq!(String::from_utf8(text).unwrap(), "11010001 10000000");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Write;
+
+use bits::BitOrder;
+use crc::crc32;
+use error::HuffmanError;
+use format::write_stream;
+use limit::build_tree_limited;
+use rle::run_length_encode;
+use tree::{TieBreak, Tree};
+use count_frequencies;
+
+/// The choices `compress_with_config` makes while compressing. The defaults write exactly what
+/// `write_compressed` does, and whatever is chosen, `read_compressed` and `read_blocks` can
+/// decode the result without being told.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EncoderConfig {
+    pub(crate) bit_order: BitOrder,
+    pub(crate) tie_break: TieBreak,
+    pub(crate) max_code_length: Option<usize>,
+    pub(crate) escape_threshold: Option<u64>,
+    pub(crate) checksum: bool,
+    pub(crate) run_length: bool,
+    pub(crate) remap: bool,
+    pub(crate) block_size: Option<usize>,
+    pub(crate) name: Option<String>,
+}
+
+impl EncoderConfig {
+    pub fn new() -> EncoderConfig {
+        EncoderConfig::default()
+    }
+
+    /// The order bits are packed into bytes, which is kept in the header.
+    pub fn bit_order(mut self, order: BitOrder) -> EncoderConfig {
+        self.bit_order = order;
+        self
+    }
+
+    /// How to choose between subtrees of equal weight while building the tree.
+    pub fn tie_break(mut self, tie_break: TieBreak) -> EncoderConfig {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// Limit codes to `max_len` bits, as `build_length_limited` does. Limited codes are
+    /// canonical, so `tie_break` then makes no difference.
+    pub fn max_code_length(mut self, max_len: usize) -> EncoderConfig {
+        self.max_code_length = Some(max_len);
+        self
+    }
+
+    /// Code bytes occurring fewer than `threshold` times with a shared escape, as
+    /// `build_tree_with_escape` does.
+    pub fn escape_threshold(mut self, threshold: u64) -> EncoderConfig {
+        self.escape_threshold = Some(threshold);
+        self
+    }
+
+    /// End each stream with the CRC-32 of the data it holds, as
+    /// `write_compressed_with_checksum` does.
+    pub fn checksum(mut self, checksum: bool) -> EncoderConfig {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Replace runs of repeated bytes before coding, as `write_compressed_rle` does.
+    pub fn run_length(mut self, run_length: bool) -> EncoderConfig {
+        self.run_length = run_length;
+        self
+    }
+
+    /// Keep the code as a `SymbolMap` of the bytes that occur and the length of each one's code,
+    /// rather than as a serialized tree, whenever that is smaller: from 43 distinct bytes on, as
+    /// long as no code is longer than `REMAP_MAX_LEN`. Ignored with an escape threshold.
+    pub fn remap(mut self, remap: bool) -> EncoderConfig {
+        self.remap = remap;
+        self
+    }
+
+    /// Split the input into blocks of `block_size` bytes, as `write_blocks` does.
+    ///
+    /// # Panics
+    ///
+    /// If `block_size` is zero.
+    pub fn block_size(mut self, block_size: usize) -> EncoderConfig {
+        assert!(block_size > 0, "Blocks must hold at least one byte");
+        self.block_size = Some(block_size);
+        self
+    }
+
+    /// Keep `name`, without any directories, in the header, as `write_compressed_named` does.
+    /// With blocks, only the first keeps it.
+    pub fn name<N: Into<String>>(mut self, name: N) -> EncoderConfig {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Build the tree for `freqs` as configured, or `None` if there is nothing to code.
+    pub(crate) fn build_tree<S: Clone + Ord + Hash>(&self, freqs: HashMap<S, u64>) -> Result<Option<Tree<S>>, HuffmanError> {
+        match self.max_code_length {
+            Some(max_len) => build_tree_limited(freqs, max_len),
+            None if freqs.is_empty() => Ok(None),
+            None => Ok(Some(Tree::from_with_tiebreak(freqs, self.tie_break))),
+        }
+    }
+}
+
+/// Compress `input` as `config` says, into one stream or, with a block size, one per block.
+pub fn compress_with_config<W: Write>(input: &[u8], mut output: W, config: &EncoderConfig) -> Result<(), HuffmanError> {
+    let mut blocks: Vec<&[u8]> = match config.block_size {
+        Some(size) => input.chunks(size).collect(),
+        None => Vec::new(),
+    };
+    if blocks.is_empty() {
+        blocks.push(input);
+    }
+
+    let unnamed = EncoderConfig { name: None, ..config.clone() };
+    for (i, block) in blocks.into_iter().enumerate() {
+        let config = if i == 0 { config } else { &unnamed };
+        let checksum = if config.checksum { Some(crc32(block)) } else { None };
+        if config.run_length {
+            let runs = run_length_encode(block);
+            write_stream(&runs[..], count_frequencies(&runs[..])?, config, checksum, &mut output)?;
+        } else {
+            write_stream(block, count_frequencies(block)?, config, checksum, &mut output)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bits::BitReader;
+    use escape::{Escaped, deserialize_escaped};
+    use format::{Header, MAGIC, decompress};
+    use testing::Xorshift;
+
+    #[test]
+    fn every_option_takes_effect() {
+        let input = Xorshift::new(91).skewed_bytes(3000);
+        let config = EncoderConfig::new()
+            .bit_order(BitOrder::MsbFirst)
+            .max_code_length(6)
+            .checksum(true)
+            .block_size(1000)
+            .name("dir/skewed.bin");
+        let mut data = Vec::new();
+        compress_with_config(&input, &mut data, &config).unwrap();
+        assert_eq!(decompress(&data).unwrap(), input);
+
+        let mut rest = &data[..];
+        let header = Header::read(&mut rest).unwrap();
+        assert_eq!(header.bit_order, BitOrder::MsbFirst);
+        assert!(header.checksum);
+        assert_eq!(header.symbol_count, 1000);
+        assert_eq!(header.name.as_deref(), Some("skewed.bin"));
+        let tree = Tree::deserialize(&mut BitReader::with_order(rest, BitOrder::MsbFirst)).unwrap();
+        assert!(tree.depth() <= 6);
+        // Unlimited, the rarest bytes get longer codes than that.
+        assert!(Tree::from(count_frequencies(&input[..1000]).unwrap()).depth() > 6);
+
+        assert_eq!(data.windows(MAGIC.len()).filter(|&window| window == MAGIC).count(), 3);
+
+        let compress = |input: &[u8], config: &EncoderConfig| {
+            let mut data = Vec::new();
+            compress_with_config(input, &mut data, config).unwrap();
+            assert_eq!(decompress(&data).unwrap(), input);
+            let mut rest = &data[..];
+            (Header::read(&mut rest).unwrap(), data.clone(), rest.to_vec())
+        };
+
+        // Weights with ties, where taking the subtree with the smallest symbol first keeps
+        // joining onto the one holding 'a'.
+        let tied = b"abccddeeeeffffgggggggg";
+        let lengths = |config: &EncoderConfig| {
+            let (_, _, rest) = compress(tied, config);
+            Tree::deserialize(&mut BitReader::new(&rest[..])).unwrap().code_lengths()
+        };
+        let by_symbol = lengths(&EncoderConfig::new().tie_break(TieBreak::BySymbol));
+        assert_ne!(by_symbol, lengths(&EncoderConfig::new()));
+        assert_eq!(by_symbol, Tree::from_with_tiebreak(count_frequencies(&tied[..]).unwrap(), TieBreak::BySymbol).code_lengths());
+
+        let (header, _, rest) = compress(&input, &EncoderConfig::new().escape_threshold(3));
+        assert!(header.escaped);
+        let tree = deserialize_escaped(&mut BitReader::new(&rest[..])).unwrap();
+        assert!(tree.code_lengths().contains_key(&Escaped::Escape));
+
+        let runs: Vec<u8> = input.iter().flat_map(|&c| vec![c; 20]).collect();
+        let (header, with_runs, _) = compress(&runs, &EncoderConfig::new().run_length(true));
+        assert!(header.run_length);
+        let (header, without_runs, _) = compress(&runs, &EncoderConfig::new());
+        assert!(!header.run_length);
+        assert!(with_runs.len() * 4 < without_runs.len(), "{} bytes with runs, {} without", with_runs.len(), without_runs.len());
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::{Write, self};
+
+/// The CRC-32 of zlib and PNG: polynomial `0xedb88320`, reflected, starting from and finishing
+/// with all bits inverted.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32 {
+    state: u32,
+}
+
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 == 1 { 0xedb8_8320 ^ (value >> 1) } else { value >> 1 };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    pub(crate) fn new() -> Crc32 {
+        Crc32 { state: !0 }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.state = TABLE[((self.state ^ u32::from(b)) & 0xff) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    pub(crate) fn value(&self) -> u32 {
+        !self.state
+    }
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+/// Passes writes through to the inner writer, keeping the CRC-32 of everything written.
+pub(crate) struct CrcWriter<W: Write> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> CrcWriter<W> {
+    pub(crate) fn new(inner: W) -> CrcWriter<W> {
+        CrcWriter { inner, crc: Crc32::new() }
+    }
+
+    pub(crate) fn crc(&self) -> u32 {
+        self.crc.value()
+    }
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_check_value_matches_zlib() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let mut writer = CrcWriter::new(Vec::new());
+        writer.write_all(b"1234").unwrap();
+        writer.write_all(b"56789").unwrap();
+        assert_eq!(writer.crc(), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,162 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use packed::PackError;
+
+/// Everything that can go wrong while building codes, encoding or decoding.
+#[derive(Debug)]
+pub enum HuffmanError {
+    /// Reading or writing failed.
+    Io(io::Error),
+    /// There were no symbols to build codes for.
+    EmptyInput,
+    /// A compressed stream's header is missing or malformed.
+    InvalidHeader(String),
+    /// Code lengths, or a coded stream, that don't describe a complete prefix code.
+    IncompletePrefixCode,
+    /// A code length outside of 1 to 64 bits.
+    InvalidCodeLength(usize),
+    /// The stream ended partway through a code or a header.
+    UnexpectedEof,
+    /// A byte to be encoded has no code in the table.
+    MissingCode(u8),
+    /// A byte occurred more often than a `u64` can count.
+    CountOverflow(u8),
+    /// Codes limited to `max_len` bits are too short to cover `symbols` symbols.
+    LengthLimitTooSmall { max_len: usize, symbols: usize, min_len: usize },
+    /// A tree no deeper than `max_depth` can't hold every symbol, which needs a depth of at least
+    /// `min_depth`.
+    DepthUnsatisfiable { max_depth: usize, min_depth: usize },
+    /// A line of a frequency table file couldn't be parsed.
+    InvalidTable { line: usize, message: String },
+    /// There were more distinct symbols than the limit given.
+    AlphabetTooLarge(usize),
+    /// The CRC-32 stored with a stream doesn't match the CRC-32 of what it decoded to.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Decoding needs room for `needed` bytes, but there is only room for `capacity`.
+    OutputTooSmall { needed: u64, capacity: usize },
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HuffmanError::Io(e) => e.fmt(f),
+            HuffmanError::EmptyInput => write!(f, "There are no symbols to build codes for"),
+            HuffmanError::InvalidHeader(message) => write!(f, "{}", message),
+            HuffmanError::IncompletePrefixCode => write!(f, "Code lengths don't form a complete prefix code"),
+            HuffmanError::InvalidCodeLength(len) => write!(f, "Code length {} isn't between 1 and 64 bits", len),
+            HuffmanError::UnexpectedEof => write!(f, "Bit stream ended early"),
+            HuffmanError::MissingCode(c) => write!(f, "No code for byte {:#04x}", c),
+            HuffmanError::CountOverflow(c) => write!(f, "Count of byte {:#04x} overflowed", c),
+            HuffmanError::LengthLimitTooSmall { max_len, symbols, min_len } => write!(
+                f,
+                "Codes of at most {} bits can't cover {} symbols; at least {} bits are needed",
+                max_len, symbols, min_len
+            ),
+            HuffmanError::DepthUnsatisfiable { max_depth, min_depth } => write!(
+                f,
+                "A tree of depth {} is too shallow for every symbol; it must be at least {} deep",
+                max_depth, min_depth
+            ),
+            HuffmanError::InvalidTable { line, message } => write!(f, "line {}: {}", line, message),
+            HuffmanError::AlphabetTooLarge(limit) => write!(f, "There are more than {} distinct symbols", limit),
+            HuffmanError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {:#010x}, decoded data has {:#010x}",
+                expected, actual
+            ),
+            HuffmanError::OutputTooSmall { needed, capacity } => write!(
+                f,
+                "Output has room for {} bytes, but {} are needed",
+                capacity, needed
+            ),
+        }
+    }
+}
+
+impl Error for HuffmanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HuffmanError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// An I/O error of kind `UnexpectedEof` means the stream was cut short, so it becomes
+/// `HuffmanError::UnexpectedEof`; anything else is kept as `HuffmanError::Io`.
+impl From<io::Error> for HuffmanError {
+    fn from(e: io::Error) -> HuffmanError {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => HuffmanError::UnexpectedEof,
+            _ => HuffmanError::Io(e),
+        }
+    }
+}
+
+/// Errors from `pack` and `unpack` as their closest `HuffmanError`. A buffer too small for the
+/// packed bits is a write that couldn't complete, as with `io::ErrorKind::WriteZero`.
+impl From<PackError> for HuffmanError {
+    fn from(e: PackError) -> HuffmanError {
+        match e {
+            PackError::MissingCode(c) => HuffmanError::MissingCode(c),
+            PackError::BufferTooSmall => HuffmanError::Io(io::Error::new(io::ErrorKind::WriteZero, e.to_string())),
+            PackError::UnexpectedEof => HuffmanError::UnexpectedEof,
+        }
+    }
+}
+
+/// For callers that work in terms of `io::Error`, such as `Read` and `Write` adaptors.
+impl From<HuffmanError> for io::Error {
+    fn from(e: HuffmanError) -> io::Error {
+        let kind = match e {
+            HuffmanError::Io(e) => return e,
+            HuffmanError::UnexpectedEof => io::ErrorKind::UnexpectedEof,
+            HuffmanError::EmptyInput
+            | HuffmanError::MissingCode(_)
+            | HuffmanError::LengthLimitTooSmall { .. }
+            | HuffmanError::DepthUnsatisfiable { .. }
+            | HuffmanError::OutputTooSmall { .. } => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use {BitWriter, Tree, compress, compress_with_table, decompress, encode};
+
+    #[test]
+    fn each_failure_has_its_own_variant() {
+        assert!(matches!(compress_with_table(&b""[..], Vec::new(), &HashMap::new()), Err(HuffmanError::EmptyInput)));
+        assert!(matches!(decompress(b"not compressed"), Err(HuffmanError::InvalidHeader(_))));
+
+        let compressed = compress(b"abracadabra, said the magician");
+        assert!(matches!(decompress(&compressed[..compressed.len() - 1]), Err(HuffmanError::UnexpectedEof)));
+
+        let lengths: HashMap<u8, usize> = [(b'a', 1), (b'b', 2)].iter().cloned().collect();
+        assert!(matches!(Tree::from_code_lengths(&lengths), Err(HuffmanError::IncompletePrefixCode)));
+
+        let table = Tree::from(lengths.keys().map(|&c| (c, 1u64)).collect::<HashMap<_, _>>()).encode();
+        assert!(matches!(encode(b"abc", &table, &mut BitWriter::new(Vec::new())), Err(HuffmanError::MissingCode(b'c'))));
+    }
+
+    #[test]
+    fn io_errors_convert_both_ways() {
+        let eof: HuffmanError = io::Error::new(io::ErrorKind::UnexpectedEof, "short").into();
+        assert!(matches!(eof, HuffmanError::UnexpectedEof));
+        let denied: HuffmanError = io::Error::new(io::ErrorKind::PermissionDenied, "denied").into();
+        assert!(denied.source().is_some());
+
+        let back: io::Error = denied.into();
+        assert_eq!(back.kind(), io::ErrorKind::PermissionDenied);
+        let back: io::Error = HuffmanError::MissingCode(0x80).into();
+        assert_eq!((back.kind(), back.to_string()), (io::ErrorKind::InvalidInput, "No code for byte 0x80".to_string()));
+        let back: io::Error = HuffmanError::IncompletePrefixCode.into();
+        assert_eq!(back.kind(), io::ErrorKind::InvalidData);
+    }
+}
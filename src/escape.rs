@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::io::{Read, Write, self};
+
+use bits::{BitReader, BitWriter};
+use error::HuffmanError;
+use tree::Tree;
+use {build_tree, next_bit, walk};
+
+/// A symbol of a tree from `build_tree_with_escape`: either a byte with its own code, or the
+/// escape that stands in for all of the rare bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Escaped {
+    Byte(u8),
+    Escape,
+}
+
+/// Build a tree like `build_tree`, except that bytes occurring fewer than `threshold` times share
+/// a single escape leaf, which is followed in the stream by the byte itself in 8 bits. Grouping a
+/// long tail of rare bytes like this keeps the tree shallower and smaller.
+pub fn build_tree_with_escape(freqs: HashMap<u8, u64>, threshold: u64) -> Option<Tree<Escaped>> {
+    build_tree(escape_frequencies(freqs, threshold))
+}
+
+/// `freqs` with the bytes occurring fewer than `threshold` times counted together as the escape.
+pub(crate) fn escape_frequencies(freqs: HashMap<u8, u64>, threshold: u64) -> HashMap<Escaped, u64> {
+    let mut escaped = HashMap::new();
+    for (c, count) in freqs {
+        let symbol = if count < threshold { Escaped::Escape } else { Escaped::Byte(c) };
+        let total = escaped.entry(symbol).or_insert(0u64);
+        *total = total.saturating_add(count);
+    }
+    escaped
+}
+
+/// Write each byte of `input` as its code from `table`, or as the escape code and the literal
+/// byte if it has no code of its own.
+pub fn encode_escaped<W: Write>(input: &[u8], table: &HashMap<Escaped, (u64, usize)>, writer: &mut BitWriter<W>) -> Result<(), HuffmanError> {
+    for &c in input {
+        if let Some(&(code, depth)) = table.get(&Escaped::Byte(c)) {
+            writer.write_code(code, depth)?;
+        } else {
+            let &(code, depth) = table.get(&Escaped::Escape).ok_or(HuffmanError::MissingCode(c))?;
+            writer.write_code(code, depth)?;
+            writer.write_bits(u64::from(c), 8)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode `count` bytes written by `encode_escaped`, reading a literal byte after each escape.
+pub fn decode_escaped<R: Read, W: Write>(reader: &mut BitReader<R>, tree: &Tree<Escaped>, count: u64, mut output: W) -> Result<(), HuffmanError> {
+    for _ in 0..count {
+        let first = next_bit(reader)?;
+        let c = match walk(reader, tree, first)? {
+            Escaped::Byte(c) => c,
+            Escaped::Escape => reader.read_bits(8)? as u8,
+        };
+        output.write_all(&[c])?;
+    }
+
+    Ok(())
+}
+
+/// Write the shape of `tree` as `Tree::serialize` does for bytes, except that each leaf's 0 bit
+/// is followed by a 1 bit for the escape, or a 0 bit and the 8-bit byte.
+pub(crate) fn serialize_escaped<W: Write>(tree: &Tree<Escaped>, w: &mut BitWriter<W>) -> Result<(), io::Error> {
+    match tree {
+        Tree::Leaf(Escaped::Byte(c), _) => {
+            w.write_bits(0, 2)?;
+            w.write_bits(u64::from(*c), 8)
+        }
+        Tree::Leaf(Escaped::Escape, _) => {
+            w.write_bits(0, 1)?;
+            w.write_bits(1, 1)
+        }
+        Tree::Node(l, r, _) => {
+            w.write_bits(1, 1)?;
+            serialize_escaped(l, w)?;
+            serialize_escaped(r, w)
+        }
+    }
+}
+
+/// The number of bits `serialize_escaped` writes.
+pub(crate) fn serialized_escaped_len(tree: &Tree<Escaped>) -> u64 {
+    match tree {
+        Tree::Leaf(Escaped::Byte(_), _) => 10,
+        Tree::Leaf(Escaped::Escape, _) => 2,
+        Tree::Node(l, r, _) => 1 + serialized_escaped_len(l) + serialized_escaped_len(r),
+    }
+}
+
+/// Read a tree written by `serialize_escaped`, with all weights zero. There are at most 257
+/// leaves, so reading stops with `AlphabetTooLarge` at more than 256 nodes.
+pub(crate) fn deserialize_escaped<R: Read>(r: &mut BitReader<R>) -> Result<Tree<Escaped>, HuffmanError> {
+    fn recurse<R: Read>(r: &mut BitReader<R>, nodes: &mut usize) -> Result<Tree<Escaped>, HuffmanError> {
+        if r.read_bits(1)? == 1 {
+            *nodes += 1;
+            if *nodes > 256 {
+                return Err(HuffmanError::AlphabetTooLarge(257));
+            }
+            let left = recurse(r, nodes)?;
+            let right = recurse(r, nodes)?;
+            Ok(left + right)
+        } else if r.read_bits(1)? == 1 {
+            Ok(Tree::Leaf(Escaped::Escape, 0))
+        } else {
+            Ok(Tree::Leaf(Escaped::Byte(r.read_bits(8)? as u8), 0))
+        }
+    }
+
+    recurse(r, &mut 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {EncoderConfig, compress, compress_with_config, count_frequencies, decompress};
+
+    /// Lots of a few common bytes, and each of a hundred others once.
+    fn long_tail() -> Vec<u8> {
+        let mut input = b"abcabcaab".repeat(50);
+        input.extend(100..200);
+        input
+    }
+
+    #[test]
+    fn rare_bytes_round_trip_through_the_escape() {
+        let input = long_tail();
+        let tree = build_tree_with_escape(count_frequencies(&input[..]).unwrap(), 2).unwrap();
+        assert_eq!(tree.leaf_count(), 4);
+
+        let table = tree.encode();
+        let mut writer = BitWriter::new(Vec::new());
+        encode_escaped(&input, &table, &mut writer).unwrap();
+        let data = writer.finish().unwrap();
+        let bits = input.iter()
+            .map(|&c| table.get(&Escaped::Byte(c)).map_or(table[&Escaped::Escape].1 + 8, |&(_, depth)| depth))
+            .sum::<usize>();
+        assert_eq!(data.len(), bits.div_ceil(8));
+
+        let mut output = Vec::new();
+        decode_escaped(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut output).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn escaped_trees_read_back_the_same() {
+        let tree = build_tree_with_escape(count_frequencies(&long_tail()[..]).unwrap(), 2).unwrap();
+        let mut writer = BitWriter::new(Vec::new());
+        serialize_escaped(&tree, &mut writer).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes.len() as u64, serialized_escaped_len(&tree).div_ceil(8));
+
+        let read = deserialize_escaped(&mut BitReader::new(&bytes[..])).unwrap();
+        assert_eq!(read.encode(), tree.encode());
+    }
+
+    #[test]
+    fn compressing_with_an_escape_round_trips() {
+        let input = long_tail();
+        let mut compressed = Vec::new();
+        compress_with_config(&input, &mut compressed, &EncoderConfig::new().escape_threshold(2)).unwrap();
+        assert!(compressed.len() < compress(&input).len());
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+}
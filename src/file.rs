@@ -0,0 +1,186 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write, self};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use config::EncoderConfig;
+use error::HuffmanError;
+use format::{read_blocks, write_stream};
+use count_frequencies;
+
+/// Sizes from compressing a file, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressStats {
+    pub input_size: u64,
+    pub output_size: u64,
+    /// Whether the input was stored as it was, because coding wouldn't have made it smaller.
+    pub stored: bool,
+}
+
+impl CompressStats {
+    /// The output size as a fraction of the input size.
+    pub fn ratio(&self) -> f64 {
+        self.output_size as f64 / self.input_size.max(1) as f64
+    }
+}
+
+/// Compress the file at `input_path` into a new file at `output_path`, in the format of
+/// `write_compressed`. The input is read twice, once to count its bytes and again to code them,
+/// so it never has to be held in memory.
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(input_path: P, output_path: Q) -> Result<CompressStats, HuffmanError> {
+    let freqs = count_frequencies(File::open(&input_path)?)?;
+    let input_size = freqs.values().sum();
+
+    let mut output = BufWriter::new(File::create(output_path)?);
+    let stored = write_stream(File::open(&input_path)?, freqs, &EncoderConfig::new(), None, &mut output)?;
+    let output = output.into_inner().map_err(|e| e.into_error())?;
+
+    Ok(CompressStats { input_size, output_size: output.metadata()?.len(), stored })
+}
+
+/// Compress `input`, which needn't be seekable, in the format of `write_compressed`. Up to
+/// `spool_threshold` bytes are held in memory to be counted and then coded; a longer input is
+/// spooled to a temporary file instead, which is read back for the second pass and removed.
+pub fn compress_reader<R: Read, W: Write>(input: R, output: W, spool_threshold: u64) -> Result<CompressStats, HuffmanError> {
+    let mut output = Counted { inner: output, written: 0 };
+    let mut input = input.take(spool_threshold.saturating_add(1));
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer)?;
+
+    let (input_size, stored) = if buffer.len() as u64 <= spool_threshold {
+        let stored = write_stream(&buffer[..], count_frequencies(&buffer[..])?, &EncoderConfig::new(), None, &mut output)?;
+        (buffer.len() as u64, stored)
+    } else {
+        let spool = Spool::create()?;
+        let mut file = &spool.file;
+        file.write_all(&buffer)?;
+        drop(buffer);
+        io::copy(&mut input.into_inner(), &mut file)?;
+
+        file.seek(SeekFrom::Start(0))?;
+        let freqs = count_frequencies(file)?;
+        file.seek(SeekFrom::Start(0))?;
+        let stored = write_stream(file, freqs.clone(), &EncoderConfig::new(), None, &mut output)?;
+        (freqs.values().sum(), stored)
+    };
+    output.flush()?;
+
+    Ok(CompressStats { input_size, output_size: output.written, stored })
+}
+
+/// A temporary file for `compress_reader`, removed when dropped.
+struct Spool {
+    file: File,
+    path: PathBuf,
+}
+
+impl Spool {
+    fn create() -> Result<Spool, io::Error> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let name = format!("rust-huffman-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+            let path = env::temp_dir().join(name);
+            match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok(Spool { file, path }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Passes writes through to the inner writer, counting the bytes written.
+struct Counted<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
+
+/// Sizes from decompressing a file, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecompressStats {
+    pub input_size: u64,
+    pub output_size: u64,
+}
+
+/// Decompress the file at `input_path`, written by `compress_file`, `write_compressed` or
+/// `write_blocks`, into a new file at `output_path`. The header of each stream is checked, and
+/// the data must hold every symbol it claims to.
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(input_path: P, output_path: Q) -> Result<DecompressStats, HuffmanError> {
+    let input = File::open(input_path)?;
+    let input_size = input.metadata()?.len();
+
+    let mut output = BufWriter::new(File::create(output_path)?);
+    let output_size = read_blocks(input, &mut output)?;
+    output.flush()?;
+
+    Ok(DecompressStats { input_size, output_size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use format::{compress, decompress};
+    use testing::Xorshift;
+
+    /// The spool files this process has left in the temporary directory.
+    fn spools() -> Vec<PathBuf> {
+        let prefix = format!("rust-huffman-{}-", process::id());
+        fs::read_dir(env::temp_dir()).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap().to_string_lossy().starts_with(&prefix))
+            .collect()
+    }
+
+    /// Reads `inner`, noting how many spool files there were when it ran out.
+    struct Watched<'a> {
+        inner: &'a [u8],
+        spools_at_end: Option<usize>,
+    }
+
+    impl<'a> Read for Watched<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.inner.read(buf)?;
+            if len == 0 {
+                self.spools_at_end = Some(spools().len());
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn inputs_in_memory_and_spooled_compress_alike() {
+        let input = Xorshift::new(99).skewed_bytes(20_000);
+        for &threshold in &[u64::MAX, 20_000, 19_999, 0] {
+            let mut reader = Watched { inner: &input, spools_at_end: None };
+            let mut output = Vec::new();
+            let stats = compress_reader(&mut reader, &mut output, threshold).unwrap();
+
+            assert_eq!(output, compress(&input));
+            assert_eq!(decompress(&output).unwrap(), input);
+            assert_eq!(stats, CompressStats { input_size: 20_000, output_size: output.len() as u64, stored: false });
+            // Spooled only past the threshold, and cleaned up after.
+            assert_eq!(reader.spools_at_end, Some(if threshold < 20_000 { 1 } else { 0 }));
+            assert!(spools().is_empty());
+        }
+    }
+}
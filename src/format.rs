@@ -0,0 +1,981 @@
+//! A compressed stream starts with a header: the magic bytes `HUF1`, a version byte, a byte of
+//! flags, and the number of symbols coded as a 64-bit integer. Multi-byte integers in headers are
+//! always big-endian. A single run of bits follows, made up of the serialized tree and then the
+//! coded data, with the final byte padded out with zeros. Empty input stops after the header. Bits
+//! are packed least significant first, unless the header is flagged for the other order, and the
+//! tree may have an escape, from `build_tree_with_escape`, if the header is flagged for that too.
+//!
+//! A tree can instead be flagged as remapped, and kept as a bitmap of the bytes that occur, lowest
+//! first, followed by the canonical code length of each of them in 4 bits, with the bytes coded
+//! as their dense indices from `SymbolMap`. For 43 or more distinct bytes that is shorter than
+//! the serialized tree.
+//!
+//! When coding wouldn't make the data any smaller, the header is flagged as stored and the
+//! symbols follow it as they are.
+//!
+//! Data can also be written without a header or tree, as only the symbol count and the coded
+//! data, for a decoder that already has the tree: from the same frequency table, or from a
+//! codebook file written by `write_codebook`, which holds `CODEBOOK_MAGIC` and the serialized tree.
+//!
+//! A header can also keep the name of the file that was compressed, after the symbol count as a
+//! 16-bit length and that many bytes of UTF-8.
+//!
+//! A stream can end with a checksum: the CRC-32 of the decoded data as a 32-bit integer, flagged
+//! in the header and checked by `read_compressed` once everything is decoded.
+//!
+//! Large inputs can be split into blocks, each written as a stream of its own with its own tree.
+//! The streams are simply concatenated. Blocks compressed by `compress_blocks_parallel` are then
+//! followed by an index of where each one starts: `INDEX_MAGIC`, each offset as a 64-bit integer,
+//! and the number of blocks as a 64-bit integer, so that `read_block_index` can find it from the
+//! end.
+
+use std::collections::HashMap;
+use std::io::{Read, Write, BufReader, Seek, SeekFrom, self};
+#[cfg(feature = "parallel")]
+use std::thread;
+
+use bits::{BitOrder, BitReader, BitWriter};
+use config::{EncoderConfig, compress_with_config};
+use crc::CrcWriter;
+use error::HuffmanError;
+use escape::{Escaped, decode_escaped, deserialize_escaped, encode_escaped, escape_frequencies, serialize_escaped, serialized_escaped_len};
+use lookup::DecodeTable;
+use remap::{REMAP_MAX_LEN, SymbolMap, Unmapper};
+use rle::RunExpander;
+use tree::Tree;
+use {build_tree, encode_reader};
+
+pub const MAGIC: [u8; 4] = *b"HUF1";
+/// The version of the layout written, which changes whenever the layout does. Version 1 had no
+/// flags or symbol count, and started the bits with a 3-bit count of padding instead.
+pub const VERSION: u8 = 2;
+
+/// The start of a codebook file from `write_codebook`.
+pub const CODEBOOK_MAGIC: [u8; 4] = *b"HUFC";
+
+/// The start of the block index that `compress_blocks_parallel` writes after the blocks.
+pub const INDEX_MAGIC: [u8; 4] = *b"HUFI";
+
+const FLAG_RUN_LENGTH: u8 = 1;
+const FLAG_STORED: u8 = 2;
+const FLAG_CHECKSUM: u8 = 4;
+const FLAG_NAME: u8 = 8;
+const FLAG_MSB_FIRST: u8 = 16;
+const FLAG_ESCAPED: u8 = 32;
+const FLAG_REMAP: u8 = 64;
+
+/// The length of a `Header` without a name once written.
+const HEADER_LEN: u64 = 14;
+
+/// The byte-aligned start of a compressed stream.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Header {
+    /// Whether the coded symbols are the pairs of `run_length_encode` rather than the data itself.
+    pub run_length: bool,
+    /// Whether the symbols follow as raw bytes instead of being coded.
+    pub stored: bool,
+    /// Whether the CRC-32 of the decoded data follows the stream.
+    pub checksum: bool,
+    /// The order the coded bits are packed in.
+    pub bit_order: BitOrder,
+    /// Whether the tree has an escape for rare bytes, each coded as the escape and the byte.
+    pub escaped: bool,
+    /// Whether the tree is kept by `SymbolMap::write_code` rather than serialized.
+    pub remapped: bool,
+    /// How many symbols were coded, which without `run_length` is how many bytes the stream
+    /// decodes to.
+    pub symbol_count: u64,
+    /// The name of the file that was compressed. Only the last component of a path is written.
+    pub name: Option<String>,
+}
+
+impl Header {
+    pub fn write<W: Write>(&self, mut output: W) -> Result<(), io::Error> {
+        let flags = (if self.run_length { FLAG_RUN_LENGTH } else { 0 })
+            | (if self.stored { FLAG_STORED } else { 0 })
+            | (if self.checksum { FLAG_CHECKSUM } else { 0 })
+            | (if self.name.is_some() { FLAG_NAME } else { 0 })
+            | (if self.bit_order == BitOrder::MsbFirst { FLAG_MSB_FIRST } else { 0 })
+            | (if self.escaped { FLAG_ESCAPED } else { 0 })
+            | (if self.remapped { FLAG_REMAP } else { 0 });
+        output.write_all(&MAGIC)?;
+        output.write_all(&[VERSION, flags])?;
+        write_u64_be(&mut output, self.symbol_count)?;
+
+        if let Some(ref name) = self.name {
+            let name = base_name(name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("\"{}\" isn't a file name", name)))?;
+            if name.len() > u16::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "File name is too long to keep"));
+            }
+            output.write_all(&(name.len() as u16).to_be_bytes())?;
+            output.write_all(name.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read a header, checking its magic and version. Any directories in a stored name are
+    /// dropped, so that it can't be used to write outside of wherever the decoder puts it.
+    pub fn read<R: Read>(mut input: R) -> Result<Header, HuffmanError> {
+        let too_short = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => HuffmanError::InvalidHeader("Stream is too short to be compressed data".to_string()),
+            _ => HuffmanError::Io(e),
+        };
+        let mut header = [0u8; 6];
+        input.read_exact(&mut header).map_err(too_short)?;
+
+        if header[..4] != MAGIC {
+            return Err(HuffmanError::InvalidHeader(format!("Expected magic \"{}\", found \"{}\"", MAGIC.escape_ascii(), header[..4].escape_ascii())));
+        }
+        if header[4] != VERSION {
+            return Err(HuffmanError::InvalidHeader(format!("Unsupported format version {} (expected {})", header[4], VERSION)));
+        }
+        let flags = header[5];
+        if flags & !(FLAG_RUN_LENGTH | FLAG_STORED | FLAG_CHECKSUM | FLAG_NAME | FLAG_MSB_FIRST | FLAG_ESCAPED | FLAG_REMAP) != 0 {
+            return Err(HuffmanError::InvalidHeader(format!("Unknown flags {:#04x}", flags)));
+        }
+        if flags & FLAG_ESCAPED != 0 && flags & FLAG_REMAP != 0 {
+            return Err(HuffmanError::InvalidHeader("An escaped tree can't be remapped".to_string()));
+        }
+
+        let symbol_count = read_u64_be(&mut input).map_err(too_short)?;
+        let name = if flags & FLAG_NAME != 0 {
+            let mut len = [0u8; 2];
+            input.read_exact(&mut len).map_err(too_short)?;
+            let mut name = vec![0u8; u16::from_be_bytes(len) as usize];
+            input.read_exact(&mut name).map_err(too_short)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| HuffmanError::InvalidHeader("Stored file name isn't UTF-8".to_string()))?;
+            match base_name(&name) {
+                Some(base) => Some(base.to_string()),
+                None => return Err(HuffmanError::InvalidHeader(format!("Stored name \"{}\" isn't a file name", name.escape_default()))),
+            }
+        } else {
+            None
+        };
+
+        Ok(Header {
+            run_length: flags & FLAG_RUN_LENGTH != 0,
+            stored: flags & FLAG_STORED != 0,
+            checksum: flags & FLAG_CHECKSUM != 0,
+            bit_order: if flags & FLAG_MSB_FIRST != 0 { BitOrder::MsbFirst } else { BitOrder::LsbFirst },
+            escaped: flags & FLAG_ESCAPED != 0,
+            remapped: flags & FLAG_REMAP != 0,
+            symbol_count,
+            name,
+        })
+    }
+}
+
+/// The last component of a path, with either kind of separator, unless there isn't one that names
+/// a file.
+pub(crate) fn base_name(path: &str) -> Option<&str> {
+    match path.rsplit(['/', '\\']).next() {
+        Some("") | Some(".") | Some("..") | None => None,
+        base => base,
+    }
+}
+
+pub(crate) fn write_u64_be<W: Write>(mut output: W, value: u64) -> Result<(), io::Error> {
+    output.write_all(&value.to_be_bytes())
+}
+
+pub(crate) fn read_u64_be<R: Read>(mut input: R) -> Result<u64, io::Error> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// The number of bytes the coded data for `freqs` takes with the codes from `tree`, without
+/// coding it. Every symbol counted in `freqs` must be in `tree`.
+pub fn predicted_size(tree: &Tree, freqs: &HashMap<u8, u64>) -> u64 {
+    data_bits(tree, freqs).div_ceil(8)
+}
+
+/// The number of bytes the header and serialized `tree` take ahead of the data. The tree doesn't
+/// end on a byte boundary, so this and `predicted_size` may add up to one more than the real size.
+pub fn predicted_header_size(tree: &Tree) -> u64 {
+    HEADER_LEN + tree.serialized_len().div_ceil(8)
+}
+
+fn data_bits(tree: &Tree, freqs: &HashMap<u8, u64>) -> u64 {
+    let codes = tree.encode_array();
+    freqs.iter()
+        .map(|(&c, &count)| count * codes[c as usize].1 as u64)
+        .sum()
+}
+
+/// Write `input` coded with a tree built from its own frequencies, along with everything needed
+/// to decode it again.
+pub fn write_compressed<W: Write>(input: &[u8], output: W) -> Result<(), HuffmanError> {
+    compress_with_config(input, output, &EncoderConfig::new())
+}
+
+/// Like `write_compressed`, but keeping `name`, without any directories, in the header, so that
+/// the decoder can recreate the file under the same name.
+pub fn write_compressed_named<W: Write>(input: &[u8], name: &str, output: W) -> Result<(), HuffmanError> {
+    compress_with_config(input, output, &EncoderConfig::new().name(name))
+}
+
+/// Compress `data` in memory, as `write_compressed` would write it.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_compressed(data, &mut output).expect("Counting a slice can't overflow, and writing to a Vec can't fail");
+    output
+}
+
+/// Decompress `data` from `compress`, or any other streams `read_blocks` can read, in memory.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+    let mut output = Vec::new();
+    read_blocks(data, &mut output)?;
+    Ok(output)
+}
+
+/// Like `write_compressed`, but ending the stream with the CRC-32 of `input`, so that
+/// `read_compressed` can tell if what it decoded isn't what was compressed.
+pub fn write_compressed_with_checksum<W: Write>(input: &[u8], output: W) -> Result<(), HuffmanError> {
+    compress_with_config(input, output, &EncoderConfig::new().checksum(true))
+}
+
+/// Like `write_compressed`, but first replacing runs of repeated bytes with pairs of the byte and
+/// the run's length, as `run_length_encode` does. This is much smaller for data with long runs,
+/// but larger for data without them. `read_compressed` undoes both steps.
+pub fn write_compressed_rle<W: Write>(input: &[u8], output: W) -> Result<(), HuffmanError> {
+    compress_with_config(input, output, &EncoderConfig::new().run_length(true))
+}
+
+/// Compress `input` as `config` says, except for splitting it into blocks. `input` must hold
+/// exactly the symbols counted in `freqs`. A `checksum`, the CRC-32 of the data before any
+/// run-length encoding, is written after the stream. Returns whether the symbols were stored
+/// rather than coded.
+pub(crate) fn write_stream<R: Read, W: Write>(mut input: R, freqs: HashMap<u8, u64>, config: &EncoderConfig, checksum: Option<u32>, mut output: W) -> Result<bool, HuffmanError> {
+    let symbol_count = freqs.values().sum::<u64>();
+    let mut header = Header {
+        run_length: config.run_length,
+        checksum: checksum.is_some(),
+        bit_order: config.bit_order,
+        symbol_count,
+        name: config.name.clone(),
+        ..Header::default()
+    };
+
+    if symbol_count == 0 {
+        header.write(&mut output)?;
+    } else if let Some(threshold) = config.escape_threshold {
+        let escaped = escape_frequencies(freqs, threshold);
+        // Each escaped byte follows the escape's code.
+        let literal_bits = 8 * u128::from(escaped.get(&Escaped::Escape).cloned().unwrap_or(0));
+        let tree = config.build_tree(escaped)?.expect("Symbols were counted");
+        if stored_is_smaller(symbol_count, serialized_escaped_len(&tree), tree.weighted_path_length() + literal_bits) {
+            header.stored = true;
+            write_stored(&header, input, &mut output)?;
+        } else {
+            header.escaped = true;
+            header.write(&mut output)?;
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            if data.len() as u64 != symbol_count {
+                return Err(input_changed());
+            }
+            let mut writer = BitWriter::with_order(&mut output, config.bit_order);
+            serialize_escaped(&tree, &mut writer)?;
+            encode_escaped(&data, &tree.encode(), &mut writer)?;
+            writer.finish()?;
+        }
+    } else {
+        let map = SymbolMap::new(&freqs);
+        let tree = config.build_tree(freqs)?.expect("Symbols were counted");
+        let remap = config.remap && tree.depth() <= REMAP_MAX_LEN && map.code_bits() < tree.serialized_len();
+        let tree_bits = if remap { map.code_bits() } else { tree.serialized_len() };
+        if stored_is_smaller(symbol_count, tree_bits, tree.weighted_path_length()) {
+            header.stored = true;
+            write_stored(&header, input, &mut output)?;
+        } else if remap {
+            header.remapped = true;
+            header.write(&mut output)?;
+            let dense = Tree::from_code_lengths(&map.dense_keys(&tree.code_lengths()))?;
+            let mut writer = BitWriter::with_order(&mut output, config.bit_order);
+            map.write_code(&dense, &mut writer)?;
+            if encode_reader(input, &map.byte_codes(&dense.encode_array()), &mut writer, |_| ())? != symbol_count {
+                return Err(input_changed());
+            }
+            writer.finish()?;
+        } else {
+            header.write(&mut output)?;
+            let mut writer = BitWriter::with_order(&mut output, config.bit_order);
+            tree.serialize(&mut writer)?;
+            if encode_reader(input, &tree.encode_array(), &mut writer, |_| ())? != symbol_count {
+                return Err(input_changed());
+            }
+            writer.finish()?;
+        }
+    }
+
+    if let Some(crc) = checksum {
+        output.write_all(&crc.to_be_bytes())?;
+    }
+    Ok(header.stored)
+}
+
+/// Whether storing `symbol_count` bytes as they are takes no more room than a tree of
+/// `tree_bits` followed by `data_bits` of codes.
+fn stored_is_smaller(symbol_count: u64, tree_bits: u64, data_bits: u128) -> bool {
+    u128::from(symbol_count) <= (u128::from(tree_bits) + data_bits).div_ceil(8)
+}
+
+/// Write `header`, flagged as stored, followed by all of `input` as it is.
+fn write_stored<R: Read, W: Write>(header: &Header, mut input: R, mut output: W) -> Result<(), HuffmanError> {
+    header.write(&mut output)?;
+    if io::copy(&mut input, &mut output)? != header.symbol_count {
+        return Err(input_changed());
+    }
+    Ok(())
+}
+
+fn input_changed() -> HuffmanError {
+    HuffmanError::Io(io::Error::new(io::ErrorKind::InvalidData, "Input changed while it was being compressed"))
+}
+
+/// Code all of `input` with a tree built from `freqs` rather than from the input itself, so that
+/// many inputs can share one codebook. Only the number of bytes coded is written ahead of the
+/// data; the decoder needs the same `freqs` to rebuild the tree. Returns the number of bytes coded.
+pub fn compress_with_table<R: Read, W: Write>(input: R, output: W, freqs: &HashMap<u8, u64>) -> Result<u64, HuffmanError> {
+    let tree = build_tree(freqs.clone()).ok_or(HuffmanError::EmptyInput)?;
+    compress_with_tree(input, output, &tree)
+}
+
+/// Like `compress_with_table`, but with the tree itself, such as one from `read_codebook`.
+pub fn compress_with_tree<R: Read, W: Write>(mut input: R, mut output: W, tree: &Tree) -> Result<u64, HuffmanError> {
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer)?;
+
+    // Check every byte has a code before writing anything, rather than leaving half a stream.
+    let codes = tree.encode_array();
+    if let Some(&c) = buffer.iter().find(|&&c| codes[c as usize].1 == 0) {
+        return Err(HuffmanError::MissingCode(c));
+    }
+
+    write_u64_be(&mut output, buffer.len() as u64)?;
+    let mut writer = BitWriter::new(output);
+    encode_reader(&buffer[..], &codes, &mut writer, |_| ())?;
+    writer.finish()?;
+    Ok(buffer.len() as u64)
+}
+
+/// Decode a stream written by `compress_with_table` with the same `freqs`, returning the number
+/// of bytes decoded. As with `read_compressed`, nothing after the stream is read.
+pub fn decompress_with_table<R: Read, W: Write>(input: R, output: W, freqs: &HashMap<u8, u64>) -> Result<u64, HuffmanError> {
+    let tree = build_tree(freqs.clone()).ok_or(HuffmanError::EmptyInput)?;
+    decompress_with_tree(input, output, &tree)
+}
+
+/// Decode a stream written by `compress_with_tree` with the same tree.
+pub fn decompress_with_tree<R: Read, W: Write>(mut input: R, output: W, tree: &Tree) -> Result<u64, HuffmanError> {
+    let symbol_count = read_u64_be(&mut input)?;
+
+    DecodeTable::new(tree, BitOrder::LsbFirst).decode(&mut BitReader::new(input), symbol_count, output)?;
+    Ok(symbol_count)
+}
+
+/// Write `tree` as a codebook, for coding data with `compress_with_tree` that is decoded
+/// elsewhere. Weights aren't kept.
+pub fn write_codebook<W: Write>(tree: &Tree, mut output: W) -> Result<(), io::Error> {
+    output.write_all(&CODEBOOK_MAGIC)?;
+    let mut writer = BitWriter::new(output);
+    tree.serialize(&mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Read a codebook written by `write_codebook`, checking that it holds a usable tree.
+pub fn read_codebook<R: Read>(mut input: R) -> Result<Tree, HuffmanError> {
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => HuffmanError::InvalidHeader("Codebook is too short".to_string()),
+        _ => HuffmanError::Io(e),
+    })?;
+    if magic != CODEBOOK_MAGIC {
+        return Err(HuffmanError::InvalidHeader(format!("Expected codebook magic \"{}\", found \"{}\"", CODEBOOK_MAGIC.escape_ascii(), magic.escape_ascii())));
+    }
+
+    let tree = Tree::deserialize(&mut BitReader::new(input))?;
+    tree.validate()?;
+    Ok(tree)
+}
+
+/// Split `input` into blocks of `block_size` bytes (the last may be shorter) and write each one
+/// as `write_compressed` would, so that each gets a tree suited to its own contents and no more
+/// than a block is held in memory at once. Empty input is still written as one empty block.
+/// Returns the number of bytes read.
+pub fn write_blocks<R: Read, W: Write>(mut input: R, mut output: W, block_size: usize) -> Result<u64, HuffmanError> {
+    assert!(block_size > 0, "Blocks must hold at least one byte");
+    let mut block = vec![0u8; block_size];
+    let mut consumed = 0u64;
+
+    loop {
+        let len = read_full(&mut input, &mut block)?;
+        if len == 0 && consumed > 0 {
+            break;
+        }
+
+        write_compressed(&block[..len], &mut output)?;
+        consumed += len as u64;
+        if len < block_size {
+            break;
+        }
+    }
+
+    Ok(consumed)
+}
+
+/// Write `input` as `write_blocks` would, compressing the blocks on a thread per available core.
+/// Each block is a whole stream, so they are simply written in order once all are done. They are
+/// followed by an index of where each block starts in the output, which is also returned. With
+/// it, `read_compressed` can decode any one block on its own, and `read_blocks` skips it.
+#[cfg(feature = "parallel")]
+pub fn compress_blocks_parallel<W: Write>(input: &[u8], mut output: W, block_size: usize) -> Result<Vec<u64>, HuffmanError> {
+    assert!(block_size > 0, "Blocks must hold at least one byte");
+    let mut blocks: Vec<&[u8]> = input.chunks(block_size).collect();
+    if blocks.is_empty() {
+        blocks.push(input);
+    }
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = blocks.len().div_ceil(threads);
+    let compressed = thread::scope(|scope| {
+        let handles: Vec<_> = blocks.chunks(per_thread)
+            .map(|group| scope.spawn(move || {
+                group.iter()
+                    .map(|block| {
+                        let mut stream = Vec::new();
+                        write_compressed(block, &mut stream)?;
+                        Ok(stream)
+                    })
+                    .collect::<Result<Vec<_>, HuffmanError>>()
+            }))
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().expect("Compressing thread panicked"))
+            .collect::<Result<Vec<_>, HuffmanError>>()
+    })?;
+
+    let mut index = Vec::with_capacity(blocks.len());
+    let mut offset = 0u64;
+    for stream in compressed.iter().flatten() {
+        index.push(offset);
+        output.write_all(stream)?;
+        offset += stream.len() as u64;
+    }
+
+    output.write_all(&INDEX_MAGIC)?;
+    for &offset in &index {
+        write_u64_be(&mut output, offset)?;
+    }
+    write_u64_be(&mut output, index.len() as u64)?;
+    Ok(index)
+}
+
+/// Read the index from the end of output written by `compress_blocks_parallel`: where each block
+/// starts, from the start of the output.
+pub fn read_block_index<R: Read + Seek>(mut input: R) -> Result<Vec<u64>, HuffmanError> {
+    let missing = || HuffmanError::InvalidHeader("There is no block index at the end of the stream".to_string());
+    let len = input.seek(SeekFrom::End(0))?;
+    if len < 12 {
+        return Err(missing());
+    }
+    input.seek(SeekFrom::End(-8))?;
+    let blocks = read_u64_be(&mut input)?;
+    let index_len = blocks.checked_mul(8).and_then(|offsets| offsets.checked_add(12))
+        .filter(|&index_len| index_len <= len)
+        .ok_or_else(missing)?;
+
+    input.seek(SeekFrom::Start(len - index_len))?;
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic)?;
+    if magic != INDEX_MAGIC {
+        return Err(missing());
+    }
+    (0..blocks).map(|_| Ok(read_u64_be(&mut input)?)).collect()
+}
+
+/// Decode every block written by `write_blocks` or `compress_blocks_parallel` in turn, until
+/// `input` ends or the block index starts. A single stream from `write_compressed` is read as one
+/// block. Returns the number of bytes decoded.
+pub fn read_blocks<R: Read, W: Write>(input: R, mut output: W) -> Result<u64, HuffmanError> {
+    let mut input = BufReader::with_capacity(1 << 16, input);
+    let mut decoded = 0u64;
+    let mut blocks = 0u64;
+    let mut start = [0u8; 4];
+    let mut len = read_full(&mut input, &mut start)?;
+    loop {
+        // The magic has been read to see what comes next, so it is put back ahead of the rest.
+        decoded += read_compressed((&start[..len]).chain(&mut input), &mut output)?;
+        blocks += 1;
+
+        len = read_full(&mut input, &mut start)?;
+        if len == 0 {
+            return Ok(decoded);
+        }
+        if start == INDEX_MAGIC {
+            skip_block_index(input, blocks)?;
+            return Ok(decoded);
+        }
+    }
+}
+
+/// Read the rest of a block index whose magic has been read, checking that it indexes `blocks`
+/// blocks and ends the input.
+fn skip_block_index<R: Read>(mut input: R, blocks: u64) -> Result<(), HuffmanError> {
+    let mut index = Vec::new();
+    input.read_to_end(&mut index)?;
+    let expected = blocks.checked_mul(8).and_then(|offsets| offsets.checked_add(8));
+    if expected != Some(index.len() as u64) || index[index.len() - 8..] != blocks.to_be_bytes() {
+        return Err(HuffmanError::InvalidHeader(format!("Block index doesn't index the {} blocks before it", blocks)));
+    }
+    Ok(())
+}
+
+/// Read until `buffer` is full or the input ends, returning how much was read. Reads interrupted
+/// by a signal are retried.
+fn read_full<R: Read>(input: &mut R, buffer: &mut [u8]) -> Result<usize, io::Error> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match input.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Decode a stream written by `write_compressed`, returning the number of bytes decoded.
+///
+/// Reading stops at the end of the stream's final byte, so if `input` is a `&mut` reference,
+/// whatever follows the stream, such as another stream, is left in it to be read next.
+///
+/// If the stream ends with a checksum and it doesn't match the decoded data, the error is
+/// `HuffmanError::ChecksumMismatch`, by which time the data has already been written to `output`.
+pub fn read_compressed<R: Read, W: Write>(mut input: R, output: W) -> Result<u64, HuffmanError> {
+    let header = Header::read(&mut input)?;
+    read_after_header(&header, input, output)
+}
+
+/// Decode the rest of a stream whose header has already been read with `Header::read`, such as to
+/// see its `name` before deciding where to write it. Otherwise the same as `read_compressed`.
+pub fn read_after_header<R: Read, W: Write>(header: &Header, mut input: R, output: W) -> Result<u64, HuffmanError> {
+    let mut output = CrcWriter::new(output);
+    let decoded = if header.run_length {
+        let mut expander = RunExpander::new(&mut output);
+        read_symbols(&mut input, header, &mut expander)?;
+        let written = expander.written();
+        expander.finish()?;
+        written
+    } else {
+        read_symbols(&mut input, header, &mut output)?;
+        header.symbol_count
+    };
+
+    if header.checksum {
+        let mut expected = [0u8; 4];
+        input.read_exact(&mut expected)?;
+        let expected = u32::from_be_bytes(expected);
+        if expected != output.crc() {
+            return Err(HuffmanError::ChecksumMismatch { expected, actual: output.crc() });
+        }
+    }
+    Ok(decoded)
+}
+
+/// Read the symbols that follow `header`, whether coded or stored.
+fn read_symbols<R: Read, W: Write>(input: R, header: &Header, mut output: W) -> Result<(), HuffmanError> {
+    if header.symbol_count == 0 {
+        return Ok(());
+    }
+    if header.stored {
+        let copied = io::copy(&mut input.take(header.symbol_count), &mut output)?;
+        return if copied == header.symbol_count { Ok(()) } else { Err(HuffmanError::UnexpectedEof) };
+    }
+
+    let mut reader = BitReader::with_order(input, header.bit_order);
+    if header.escaped {
+        let tree = deserialize_escaped(&mut reader)?;
+        tree.validate()?;
+        return decode_escaped(&mut reader, &tree, header.symbol_count, output);
+    }
+    if header.remapped {
+        let (map, tree) = SymbolMap::read_code(&mut reader)?;
+        return DecodeTable::new(&tree, header.bit_order).decode(&mut reader, header.symbol_count, Unmapper::new(&map, output));
+    }
+    let tree = Tree::deserialize(&mut reader)?;
+    tree.validate()?;
+    DecodeTable::new(&tree, header.bit_order).decode(&mut reader, header.symbol_count, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crc::crc32;
+    use testing::Xorshift;
+    use {count_frequencies, encode};
+
+    #[test]
+    fn a_lone_symbol_round_trips_with_one_bit_codes() {
+        let input = vec![b'z'; 1000];
+        let compressed = compress(&input);
+        // The header, the 9-bit tree, and a bit for each byte.
+        assert_eq!(compressed.len() as u64, HEADER_LEN + (9 + 1000u64).div_ceil(8));
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn padding_doesnt_decode_as_phantom_symbols() {
+        // 19 bits of tree and 26 of data leave 3 bits of zero padding, which would read as three
+        // more of the lighter 'b', coded as 0.
+        let mut input = vec![b'a'; 25];
+        input.push(b'b');
+        let compressed = compress(&input);
+        assert_eq!(compressed.len() as u64, HEADER_LEN + 6);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    fn header_error(data: &[u8]) -> String {
+        match Header::read(data) {
+            Err(HuffmanError::InvalidHeader(message)) => message,
+            other => panic!("Expected InvalidHeader, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn headers_read_back_as_written() {
+        let header = Header { checksum: true, symbol_count: 1 << 40, name: Some("notes.txt".to_string()), ..Header::default() };
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(bytes[..5], [b'H', b'U', b'F', b'1', VERSION]);
+        assert_eq!(Header::read(&bytes[..]).unwrap(), header);
+    }
+
+    #[test]
+    fn wrong_magic_is_rejected() {
+        let mut compressed = compress(b"hello, world");
+        compressed[..4].copy_from_slice(b"PK\x03\x04");
+        assert_eq!(header_error(&compressed), "Expected magic \"HUF1\", found \"PK\\x03\\x04\"");
+        assert_eq!(header_error(b"HU"), "Stream is too short to be compressed data");
+    }
+
+    #[test]
+    fn future_versions_are_rejected() {
+        let mut compressed = compress(b"hello, world");
+        compressed[4] = VERSION + 1;
+        assert_eq!(header_error(&compressed), format!("Unsupported format version {} (expected {})", VERSION + 1, VERSION));
+    }
+
+    #[test]
+    fn the_header_records_the_symbol_count() {
+        for &len in &[0usize, 1, 7, 1000] {
+            let input: Vec<u8> = (0..len).map(|i| b"etaoin shrdlu"[i % 13]).collect();
+            let compressed = compress(&input);
+            assert_eq!(Header::read(&compressed[..]).unwrap().symbol_count, len as u64);
+
+            let mut output = Vec::new();
+            assert_eq!(read_compressed(&compressed[..], &mut output).unwrap(), len as u64);
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn header_integers_are_big_endian() {
+        let mut bytes = Vec::new();
+        write_u64_be(&mut bytes, 0x0102_0304_0506_0708).unwrap();
+        assert_eq!(bytes, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(read_u64_be(&bytes[..]).unwrap(), 0x0102_0304_0506_0708);
+
+        let header = Header { symbol_count: 0x0102, name: Some("ab".to_string()), ..Header::default() };
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(bytes[6..], [0, 0, 0, 0, 0, 0, 1, 2, 0, 2, b'a', b'b']);
+        assert_eq!(Header::read(&bytes[..]).unwrap(), header);
+    }
+
+    #[test]
+    fn inputs_share_a_table() {
+        let freqs = count_frequencies(&b"the quick brown fox jumps over the lazy dog"[..]).unwrap();
+        for input in [&b"a lazy fox"[..], b"the brown dog jumps"] {
+            let mut compressed = Vec::new();
+            assert_eq!(compress_with_table(input, &mut compressed, &freqs).unwrap(), input.len() as u64);
+            // Only the count and the data, with no header or tree.
+            let bits: usize = input.iter().map(|c| build_tree(freqs.clone()).unwrap().encode()[c].1).sum();
+            assert_eq!(compressed.len(), 8 + bits.div_ceil(8));
+
+            let mut output = Vec::new();
+            assert_eq!(decompress_with_table(&compressed[..], &mut output, &freqs).unwrap(), input.len() as u64);
+            assert_eq!(output, input);
+        }
+
+        let mut compressed = Vec::new();
+        assert!(matches!(compress_with_table(&b"cats!"[..], &mut compressed, &freqs), Err(HuffmanError::MissingCode(b'!'))));
+        assert!(compressed.is_empty());
+    }
+
+    #[test]
+    fn concatenated_streams_are_read_one_at_a_time() {
+        let (first, second) = (compress(b"abracadabra, abracadabra"), compress(b"the quick brown fox"));
+        assert!(!Header::read(&first[..]).unwrap().stored);
+        let both = [&first[..], &second[..]].concat();
+
+        let mut input = &both[..];
+        let mut output = Vec::new();
+        read_compressed(&mut input, &mut output).unwrap();
+        assert_eq!(output, b"abracadabra, abracadabra");
+        // Nothing of the second stream has been read.
+        assert_eq!(input, &second[..]);
+
+        assert_eq!(decompress(&both).unwrap(), b"abracadabra, abracadabrathe quick brown fox");
+    }
+
+    #[test]
+    fn run_length_coding_shrinks_long_runs_further() {
+        let mut input = Vec::new();
+        for (i, &c) in b"abcdefgh".iter().cycle().take(64).enumerate() {
+            input.extend(vec![c; 100 + 37 * i % 300]);
+        }
+
+        let mut rle = Vec::new();
+        write_compressed_rle(&input, &mut rle).unwrap();
+        assert!(Header::read(&rle[..]).unwrap().run_length);
+        assert!(rle.len() * 4 < compress(&input).len(), "{} bytes with runs, {} without", rle.len(), compress(&input).len());
+        assert_eq!(decompress(&rle).unwrap(), input);
+    }
+
+    #[test]
+    fn predicted_sizes_match_what_is_written() {
+        let mut random = Xorshift::new(51);
+        for len in [50, 333, 4096] {
+            let input = random.skewed_bytes(len);
+            let freqs = count_frequencies(&input[..]).unwrap();
+            let tree = build_tree(freqs.clone()).unwrap();
+
+            let mut writer = BitWriter::new(Vec::new());
+            encode(&input, &tree.encode(), &mut writer).unwrap();
+            assert_eq!(writer.finish().unwrap().len() as u64, predicted_size(&tree, &freqs));
+
+            // The tree and data share a byte unless the tree happens to end on a boundary.
+            let compressed = compress(&input).len() as u64;
+            let predicted = predicted_header_size(&tree) + predicted_size(&tree, &freqs);
+            assert!(predicted == compressed || predicted == compressed + 1, "{} predicted, {} written", predicted, compressed);
+        }
+    }
+
+    #[test]
+    fn random_data_is_stored() {
+        let input = Xorshift::new(52).bytes(2000, 256);
+        let compressed = compress(&input);
+        assert!(Header::read(&compressed[..]).unwrap().stored);
+        assert_eq!(compressed.len() as u64, HEADER_LEN + 2000);
+        assert_eq!(compressed[HEADER_LEN as usize..], input[..]);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+
+        // So is anything too short for its tree to pay for itself.
+        assert!(Header::read(&compress(b"abc")[..]).unwrap().stored);
+    }
+
+    /// The symbols of the tree of the coded stream at the start of `data`.
+    fn stream_symbols(mut data: &[u8]) -> Vec<u8> {
+        assert!(!Header::read(&mut data).unwrap().stored);
+        Tree::deserialize(&mut BitReader::new(data)).unwrap().symbols().collect()
+    }
+
+    #[test]
+    fn each_block_gets_its_own_tree() {
+        let (first, second) = (b"abacabad".repeat(100), b"wxyzzyxz".repeat(100));
+        let input = [&first[..], &second[..]].concat();
+        let mut compressed = Vec::new();
+        assert_eq!(write_blocks(&input[..], &mut compressed, first.len()).unwrap(), input.len() as u64);
+
+        let mut rest = &compressed[..];
+        let mut sorted = stream_symbols(rest);
+        sorted.sort();
+        assert_eq!(sorted, b"abcd");
+        let mut output = Vec::new();
+        read_compressed(&mut rest, &mut output).unwrap();
+        assert_eq!(output, first);
+
+        let mut sorted = stream_symbols(rest);
+        sorted.sort();
+        assert_eq!(sorted, b"wxyz");
+        assert_eq!(decompress(rest).unwrap(), second);
+
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn corrupt_data_fails_the_checksum() {
+        // Every code two bits long, so that a flipped bit changes a symbol but not where the
+        // codes after it start.
+        let input = b"abcd".repeat(250);
+        let mut data = Vec::new();
+        write_compressed_with_checksum(&input, &mut data).unwrap();
+        assert_eq!(decompress(&data).unwrap(), input);
+
+        let middle = data.len() / 2;
+        data[middle] ^= 0x04;
+        match decompress(&data) {
+            Err(HuffmanError::ChecksumMismatch { expected, actual }) => {
+                assert_eq!(expected, crc32(&input));
+                assert_ne!(actual, expected);
+            }
+            other => panic!("Expected ChecksumMismatch, got {:?}", other),
+        }
+
+        // Without a checksum the same damage goes unnoticed.
+        let mut data = compress(&input);
+        data[middle] ^= 0x04;
+        assert_ne!(decompress(&data).unwrap(), input);
+    }
+
+    #[test]
+    fn random_inputs_round_trip_in_memory() {
+        let mut random = Xorshift::new(86);
+        for round in 0..200 {
+            let len = match round {
+                0 => 0,
+                1 => 1,
+                _ => (random.next_u64() % 3000) as usize,
+            };
+            let limit = 1 + random.next_u64() % 8;
+            let input = match round % 3 {
+                0 => random.bytes(len, 256),
+                1 => random.bytes(len, limit),
+                _ => random.skewed_bytes(len),
+            };
+            assert_eq!(decompress(&compress(&input)).unwrap(), input, "round {}, {} bytes", round, len);
+        }
+    }
+
+    #[test]
+    fn stored_names_lose_their_directories() {
+        assert_eq!(base_name("foo.txt"), Some("foo.txt"));
+        assert_eq!(base_name("/home/user/foo.txt"), Some("foo.txt"));
+        assert_eq!(base_name("..\\..\\foo.txt"), Some("foo.txt"));
+        for &path in &["", "dir/", ".", "../..", "C:\\"] {
+            assert_eq!(base_name(path), None, "{:?}", path);
+        }
+
+        // `Header::write` won't store such names, so swap them in for a plain one of the same length.
+        let named = |name: &str| {
+            let plain = "x".repeat(name.len());
+            let mut bytes = Vec::new();
+            Header { name: Some(plain.clone()), ..Header::default() }.write(&mut bytes).unwrap();
+            let at = bytes.len() - name.len();
+            assert_eq!(&bytes[at..], plain.as_bytes());
+            bytes[at..].copy_from_slice(name.as_bytes());
+            bytes
+        };
+        assert_eq!(Header::read(&named("../../etc/passwd")[..]).unwrap().name.unwrap(), "passwd");
+        assert_eq!(header_error(&named("/tmp/..")), "Stored name \"/tmp/..\" isn't a file name");
+
+        let mut data = Vec::new();
+        write_compressed_named(b"hello", "docs/notes.txt", &mut data).unwrap();
+        assert_eq!(Header::read(&data[..]).unwrap().name.unwrap(), "notes.txt");
+    }
+
+    /// `len` bytes spread evenly over `k` distinct values, each 5 apart.
+    fn sparse_bytes(random: &mut Xorshift, len: usize, k: u64) -> Vec<u8> {
+        random.bytes(len, k).into_iter().map(|i| i * 5 + 1).collect()
+    }
+
+    #[test]
+    fn sparse_alphabets_round_trip_remapped() {
+        let mut random = Xorshift::new(78);
+        for order in [BitOrder::LsbFirst, BitOrder::MsbFirst] {
+            let input = sparse_bytes(&mut random, 5000, 50);
+            let config = EncoderConfig::new().bit_order(order).remap(true);
+            let mut data = Vec::new();
+            compress_with_config(&input, &mut data, &config).unwrap();
+            assert!(Header::read(&data[..]).unwrap().remapped);
+            assert_eq!(decompress(&data).unwrap(), input);
+        }
+
+        // Run-length pairs are bytes like any other.
+        let input: Vec<u8> = sparse_bytes(&mut random, 2000, 50).into_iter().flat_map(|c| vec![c; 3]).collect();
+        let mut data = Vec::new();
+        compress_with_config(&input, &mut data, &EncoderConfig::new().run_length(true).remap(true)).unwrap();
+        assert!(Header::read(&data[..]).unwrap().remapped);
+        assert_eq!(decompress(&data).unwrap(), input);
+
+        // A remapped tree can't have an escape in it.
+        data[5] |= FLAG_ESCAPED;
+        assert_eq!(header_error(&data), "An escaped tree can't be remapped");
+    }
+
+    #[test]
+    fn remapping_shrinks_the_header_from_43_bytes() {
+        let mut random = Xorshift::new(43);
+        for k in [42, 43, 51] {
+            let input = sparse_bytes(&mut random, 3000, k);
+            let tree = build_tree(count_frequencies(&input[..]).unwrap()).unwrap();
+            let mut plain = Vec::new();
+            compress_with_config(&input, &mut plain, &EncoderConfig::new()).unwrap();
+            let mut remapped = Vec::new();
+            compress_with_config(&input, &mut remapped, &EncoderConfig::new().remap(true)).unwrap();
+            assert_eq!(decompress(&remapped).unwrap(), input);
+
+            // The same codes follow the serialized tree of 10k - 1 bits, or 256 + 4k bits of map.
+            let coded_len = |tree_bits: u64| HEADER_LEN as u128 + (u128::from(tree_bits) + tree.weighted_path_length()).div_ceil(8);
+            assert_eq!(plain.len() as u128, coded_len(10 * k - 1));
+            if k < 43 {
+                assert!(!Header::read(&remapped[..]).unwrap().remapped);
+                assert_eq!(remapped, plain);
+            } else {
+                assert!(Header::read(&remapped[..]).unwrap().remapped);
+                assert_eq!(remapped.len() as u128, coded_len(256 + 4 * k));
+                assert!(remapped.len() < plain.len());
+            }
+        }
+    }
+
+    #[test]
+    fn long_codes_are_not_remapped() {
+        // Fibonacci counts give the rarest bytes codes of around 20 bits.
+        let (mut a, mut b) = (1, 1);
+        let mut input = Vec::new();
+        for i in 0..20u8 {
+            input.extend(vec![i * 5; a]);
+            (a, b) = (b, a + b);
+        }
+        for i in 20..50u8 {
+            input.extend(vec![i * 5; 1000]);
+        }
+        assert!(build_tree(count_frequencies(&input[..]).unwrap()).unwrap().depth() > REMAP_MAX_LEN);
+
+        let mut data = Vec::new();
+        compress_with_config(&input, &mut data, &EncoderConfig::new().remap(true)).unwrap();
+        assert!(!Header::read(&data[..]).unwrap().remapped);
+        assert_eq!(decompress(&data).unwrap(), input);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_blocks_decode_as_serial_blocks_do() {
+        let mut random = Xorshift::new(95);
+        let input = [random.skewed_bytes(5000), random.bytes(3000, 256), b"abc".repeat(2000)].concat();
+        let mut serial = Vec::new();
+        write_blocks(&input[..], &mut serial, 4096).unwrap();
+        let mut parallel = Vec::new();
+        let index = compress_blocks_parallel(&input, &mut parallel, 4096).unwrap();
+
+        // The same blocks, followed by the index.
+        assert_eq!(parallel[..serial.len()], serial[..]);
+        assert_eq!(index.len(), input.len().div_ceil(4096));
+        assert_eq!(read_block_index(io::Cursor::new(&parallel)).unwrap(), index);
+        assert_eq!(decompress(&parallel).unwrap(), input);
+
+        // Each block decodes on its own from where the index says it starts.
+        for (i, &start) in index.iter().enumerate() {
+            let mut block = Vec::new();
+            read_compressed(&parallel[start as usize..], &mut block).unwrap();
+            assert_eq!(block, input.chunks(4096).nth(i).unwrap());
+        }
+    }
+}
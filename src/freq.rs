@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{Read, Write, BufRead, BufReader, self};
+#[cfg(feature = "parallel")]
+use std::thread;
+
+use error::HuffmanError;
+
+/// Count how many times each byte occurs in `input`.
+///
+/// The input is read a buffer at a time until a read returns no bytes, however few each read
+/// returns before then. Reads interrupted by a signal are retried.
+pub fn count_frequencies<R: Read>(mut input: R) -> Result<HashMap<u8, u64>, HuffmanError> {
+    let mut counts = [0u64; 256];
+    let mut buffer = vec![0u8; 1 << 16];
+
+    loop {
+        let len = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        // A buffer's counts can't overflow on their own, so only the running totals need checking.
+        for (c, (total, &count)) in counts.iter_mut().zip(histogram(&buffer[..len]).iter()).enumerate() {
+            *total = total.checked_add(count).ok_or(HuffmanError::CountOverflow(c as u8))?;
+        }
+    }
+
+    Ok(counts_to_map(&counts))
+}
+
+/// How many times each byte occurs in `chunk`, indexed by byte.
+fn histogram(chunk: &[u8]) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for &c in chunk {
+        counts[c as usize] += 1;
+    }
+    counts
+}
+
+/// The bytes counted at least once in `counts`, indexed by byte, with their counts.
+fn counts_to_map(counts: &[u64; 256]) -> HashMap<u8, u64> {
+    (0..=255u8).zip(counts.iter())
+        .filter(|&(_, &count)| count > 0)
+        .map(|(c, &count)| (c, count))
+        .collect()
+}
+
+/// Count how many times each byte occurs in `input`, as `count_frequencies` does, by splitting it
+/// into a chunk per available core and counting each chunk on its own thread. Inputs too small
+/// to be worth splitting are counted on the calling thread.
+#[cfg(feature = "parallel")]
+pub fn count_frequencies_parallel(input: &[u8]) -> HashMap<u8, u64> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_len = input.len().div_ceil(threads).max(1 << 20);
+    let counts = thread::scope(|scope| {
+        let handles: Vec<_> = input.chunks(chunk_len)
+            .map(|chunk| scope.spawn(move || histogram(chunk)))
+            .collect();
+        let mut counts = [0u64; 256];
+        for handle in handles {
+            let chunk_counts = handle.join().expect("Counting thread panicked");
+            for (total, count) in counts.iter_mut().zip(&chunk_counts) {
+                *total += count;
+            }
+        }
+        counts
+    });
+
+    counts_to_map(&counts)
+}
+
+/// Count the symbols that `tokenizer` makes out of `input`, for coding something other than
+/// single bytes with a `Tree<S>`. The tokenizer is handed each byte in turn and returns a symbol
+/// whenever one is complete, so it can hold state between bytes, such as the previous byte for
+/// digrams. Anything it still holds when the input ends is not counted.
+pub fn count_tokens<R, S, F>(input: R, tokenizer: F) -> Result<HashMap<S, u64>, HuffmanError>
+    where R: Read, S: Eq + Hash, F: FnMut(u8) -> Option<S>
+{
+    count_tokens_capped(input, tokenizer, usize::MAX)
+}
+
+/// Like `count_tokens`, but failing with `AlphabetTooLarge` as soon as more than `max_symbols`
+/// distinct symbols turn up, so that untrusted input can't grow the map without bound.
+pub fn count_tokens_capped<R, S, F>(input: R, mut tokenizer: F, max_symbols: usize) -> Result<HashMap<S, u64>, HuffmanError>
+    where R: Read, S: Eq + Hash, F: FnMut(u8) -> Option<S>
+{
+    let mut map = HashMap::new();
+
+    let input = BufReader::with_capacity(1 << 16, input);
+    for c in input.bytes() {
+        if let Some(symbol) = tokenizer(c?) {
+            if map.len() == max_symbols && !map.contains_key(&symbol) {
+                return Err(HuffmanError::AlphabetTooLarge(max_symbols));
+            }
+            *map.entry(symbol).or_insert(0u64) += 1;
+        }
+    }
+
+    Ok(map)
+}
+
+/// Count how many times each byte occurs across all of `inputs` together, such as when training
+/// one table to share between several similar files.
+pub fn count_frequencies_many<I, R>(inputs: I) -> Result<HashMap<u8, u64>, HuffmanError>
+    where I: IntoIterator<Item = R>, R: Read
+{
+    let mut map = HashMap::new();
+
+    for input in inputs {
+        merge_frequencies(&mut map, &count_frequencies(input)?)?;
+    }
+
+    Ok(map)
+}
+
+/// Add the counts in `b` to those in `a`, such as when combining counts of streams made
+/// separately. If a sum overflows, `a` is left with only some of `b` added.
+pub fn merge_frequencies(a: &mut HashMap<u8, u64>, b: &HashMap<u8, u64>) -> Result<(), HuffmanError> {
+    for (&c, &count) in b {
+        let seen = a.entry(c).or_insert(0u64);
+        *seen = seen.checked_add(count).ok_or(HuffmanError::CountOverflow(c))?;
+    }
+
+    Ok(())
+}
+
+/// Write a frequency table in the text format `load_table` reads: one `<symbol> <count>` line per
+/// byte, in order of symbol, with the symbol in hex.
+pub fn save_table<W: Write>(freqs: &HashMap<u8, u64>, mut output: W) -> Result<(), io::Error> {
+    let mut entries: Vec<_> = freqs.iter().collect();
+    entries.sort();
+    for (c, count) in entries {
+        writeln!(output, "{:#04x} {}", c, count)?;
+    }
+
+    Ok(())
+}
+
+/// Read a frequency table of `<symbol> <count>` lines, as written by `save_table`. Symbols may be
+/// decimal or `0x`-prefixed hex. Blank lines and lines starting with `#` are skipped. The counts
+/// must add up to no more than a `u64` can hold, as the counts of any real input do.
+pub fn load_table<R: Read>(input: R) -> Result<HashMap<u8, u64>, HuffmanError> {
+    let mut map = HashMap::new();
+    let mut total = 0u64;
+
+    for (number, line) in BufReader::new(input).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |message: String| HuffmanError::InvalidTable { line: number + 1, message };
+        let mut fields = line.split_whitespace();
+        let (symbol, count) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(symbol), Some(count), None) => (symbol, count),
+            _ => return Err(error(format!("expected '<symbol> <count>', found '{}'", line))),
+        };
+
+        let symbol = match symbol.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => symbol.parse(),
+        }.map_err(|_| error(format!("'{}' is not a byte", symbol)))?;
+        let count: u64 = count.parse()
+            .map_err(|_| error(format!("'{}' is not a count", count)))?;
+
+        if map.insert(symbol, count).is_some() {
+            return Err(error(format!("symbol {:#04x} is listed twice", symbol)));
+        }
+        total = total.checked_add(count)
+            .ok_or_else(|| error("counts add up to more than a 64-bit count can hold".to_string()))?;
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    #[cfg(feature = "parallel")]
+    use testing::Xorshift;
+
+    #[test]
+    fn counts_each_byte_of_a_cursor() {
+        let freqs = count_frequencies(Cursor::new(b"mississippi".to_vec())).unwrap();
+        let expected: HashMap<u8, u64> = [(b'm', 1), (b'i', 4), (b's', 4), (b'p', 2)].iter().cloned().collect();
+        assert_eq!(freqs, expected);
+        assert!(count_frequencies(Cursor::new(Vec::new())).unwrap().is_empty());
+
+        // More than one buffer's worth.
+        let freqs = count_frequencies(Cursor::new(vec![b'x'; 200_000])).unwrap();
+        assert_eq!(freqs[&b'x'], 200_000);
+    }
+
+    #[test]
+    fn merging_adds_the_counts_of_overlapping_maps() {
+        let mut a: HashMap<u8, u64> = [(b'a', 3), (b'b', 1)].iter().cloned().collect();
+        let b: HashMap<u8, u64> = [(b'b', 2), (b'c', 5)].iter().cloned().collect();
+        merge_frequencies(&mut a, &b).unwrap();
+        let expected: HashMap<u8, u64> = [(b'a', 3), (b'b', 3), (b'c', 5)].iter().cloned().collect();
+        assert_eq!(a, expected);
+    }
+
+    #[test]
+    fn merged_counts_past_u64_max_are_an_error() {
+        let mut a: HashMap<u8, u64> = [(b'a', u64::MAX - 1)].iter().cloned().collect();
+        let b: HashMap<u8, u64> = [(b'a', 2)].iter().cloned().collect();
+        match merge_frequencies(&mut a, &b) {
+            Err(HuffmanError::CountOverflow(b'a')) => {}
+            other => panic!("Expected CountOverflow, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counts_across_inputs_are_merged() {
+        let freqs = count_frequencies_many(vec![Cursor::new(b"abba".to_vec()), Cursor::new(b"cab".to_vec())]).unwrap();
+        let expected: HashMap<u8, u64> = [(b'a', 3), (b'b', 3), (b'c', 1)].iter().cloned().collect();
+        assert_eq!(freqs, expected);
+    }
+
+    fn table_error(table: &str) -> (usize, String) {
+        match load_table(table.as_bytes()) {
+            Err(HuffmanError::InvalidTable { line, message }) => (line, message),
+            other => panic!("Expected InvalidTable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn saved_tables_load_back_the_same() {
+        let freqs = count_frequencies(&b"the quick brown fox jumps over the lazy dog\n\x00\xff"[..]).unwrap();
+        let mut table = Vec::new();
+        save_table(&freqs, &mut table).unwrap();
+        assert_eq!(load_table(&table[..]).unwrap(), freqs);
+
+        let written = "# comment\n\n97 3\n0x62 1\n";
+        let expected: HashMap<u8, u64> = [(b'a', 3), (b'b', 1)].iter().cloned().collect();
+        assert_eq!(load_table(written.as_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_by_number() {
+        assert_eq!(table_error("97 3\n98\n"), (2, "expected '<symbol> <count>', found '98'".to_string()));
+        assert_eq!(table_error("256 3\n"), (1, "'256' is not a byte".to_string()));
+        assert_eq!(table_error("# counts\n97 -3\n"), (2, "'-3' is not a count".to_string()));
+        assert_eq!(table_error("97 3\n0x61 4\n"), (2, "symbol 0x61 is listed twice".to_string()));
+    }
+
+    #[test]
+    fn counts_past_u64_max_are_reported_by_line() {
+        let table = format!("97 {}\n98 1\n99 {}\n", u64::MAX / 2, u64::MAX / 2 + 1);
+        assert_eq!(table_error(&table), (3, "counts add up to more than a 64-bit count can hold".to_string()));
+    }
+
+    #[test]
+    fn digrams_are_counted_as_symbols() {
+        let mut previous = None;
+        let digrams = count_tokens(&b"banana"[..], |c| {
+            let digram = previous.map(|p| (p, c));
+            previous = Some(c);
+            digram
+        }).unwrap();
+        let expected: HashMap<(u8, u8), u64> = [((b'b', b'a'), 1), ((b'a', b'n'), 2), ((b'n', b'a'), 2)].iter().cloned().collect();
+        assert_eq!(digrams, expected);
+
+        // Pairs that don't overlap, with the odd byte out left uncounted.
+        let mut held = None;
+        let pairs = count_tokens(&b"abcab"[..], |c| match held.take() {
+            Some(p) => Some((p, c)),
+            None => {
+                held = Some(c);
+                None
+            }
+        }).unwrap();
+        let expected: HashMap<(u8, u8), u64> = [((b'a', b'b'), 1), ((b'c', b'a'), 1)].iter().cloned().collect();
+        assert_eq!(pairs, expected);
+    }
+
+    /// Reads `inner`, but fails with `Interrupted` before the first read, as a read cut short by
+    /// a signal does.
+    struct InterruptedOnce<R> {
+        inner: R,
+        interrupted: bool,
+    }
+
+    impl<R: Read> Read for InterruptedOnce<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "signal"));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    #[test]
+    fn interrupted_reads_are_retried() {
+        let freqs = count_frequencies(InterruptedOnce { inner: &b"mississippi"[..], interrupted: false }).unwrap();
+        assert_eq!(freqs, count_frequencies(&b"mississippi"[..]).unwrap());
+
+        let tokens = count_tokens(InterruptedOnce { inner: &b"mississippi"[..], interrupted: false }, Some).unwrap();
+        assert_eq!(tokens, freqs);
+    }
+
+    #[test]
+    fn capped_tokens_stop_at_one_symbol_too_many() {
+        let capped = count_tokens_capped(&b"abcabc"[..], Some, 3).unwrap();
+        assert_eq!(capped, count_frequencies(&b"abcabc"[..]).unwrap());
+
+        match count_tokens_capped(&b"abcabd"[..], Some, 3) {
+            Err(HuffmanError::AlphabetTooLarge(3)) => {}
+            other => panic!("Expected AlphabetTooLarge, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_counts_match_serial_counts() {
+        // Split into several chunks on any machine with more than one core.
+        let mut random = Xorshift::new(71);
+        let input = [random.bytes(5 << 20, 256), random.skewed_bytes(1 << 20)].concat();
+        assert_eq!(count_frequencies_parallel(&input), count_frequencies(&input[..]).unwrap());
+        assert_eq!(count_frequencies_parallel(b"abba"), count_frequencies(&b"abba"[..]).unwrap());
+        assert!(count_frequencies_parallel(b"").is_empty());
+    }
+
+    /// Hands out `inner` a few bytes at a time, never filling the buffer it is given.
+    struct Dribble<'a> {
+        inner: &'a [u8],
+        next_len: usize,
+    }
+
+    impl<'a> Read for Dribble<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.next_len = self.next_len % 7 + 1;
+            let len = self.next_len.min(buf.len()).min(self.inner.len());
+            buf[..len].copy_from_slice(&self.inner[..len]);
+            self.inner = &self.inner[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn short_reads_are_counted_until_the_end() {
+        let input = b"she sells sea shells by the sea shore".repeat(100);
+        let freqs = count_frequencies(Dribble { inner: &input, next_len: 0 }).unwrap();
+        assert_eq!(freqs, count_frequencies(&input[..]).unwrap());
+        assert_eq!(freqs.values().sum::<u64>(), input.len() as u64);
+    }
+}
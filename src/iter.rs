@@ -0,0 +1,74 @@
+use std::convert::TryFrom;
+use std::io::Read;
+
+use bits::BitReader;
+use error::HuffmanError;
+use tree::Tree;
+use {next_bit, walk};
+
+/// Decodes bytes one at a time as they are asked for; see `decode_iter`.
+pub struct DecodeIter<'a, R: Read + 'a> {
+    reader: &'a mut BitReader<R>,
+    tree: &'a Tree,
+    remaining: u64,
+}
+
+/// Decode `count` bytes from `reader` lazily, reading only the bits each byte needs as it is
+/// produced. An error, such as the stream ending partway through a code, is yielded once, after
+/// which the iterator ends.
+pub fn decode_iter<'a, R: Read>(reader: &'a mut BitReader<R>, tree: &'a Tree, count: u64) -> DecodeIter<'a, R> {
+    DecodeIter { reader, tree, remaining: count }
+}
+
+impl<'a, R: Read> Iterator for DecodeIter<'a, R> {
+    type Item = Result<u8, HuffmanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let c = next_bit(self.reader).and_then(|first| walk(self.reader, self.tree, first));
+        self.remaining = if c.is_ok() { self.remaining - 1 } else { 0 };
+        Some(c)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, usize::try_from(self.remaining).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {BitWriter, build_tree, count_frequencies, encode, encoding_table};
+
+    fn coded(input: &[u8]) -> (Tree, Vec<u8>) {
+        let tree = build_tree(count_frequencies(input).unwrap()).unwrap();
+        let mut writer = BitWriter::new(Vec::new());
+        encode(input, &encoding_table(&tree), &mut writer).unwrap();
+        (tree, writer.finish().unwrap())
+    }
+
+    #[test]
+    fn the_iterator_yields_the_original_bytes() {
+        let input = b"she sells sea shells by the sea shore";
+        let (tree, data) = coded(input);
+        let mut reader = BitReader::new(&data[..]);
+        let output: Result<Vec<u8>, _> = decode_iter(&mut reader, &tree, input.len() as u64).collect();
+        assert_eq!(output.unwrap(), &input[..]);
+    }
+
+    #[test]
+    fn a_cut_stream_yields_one_error_then_ends() {
+        let input = b"she sells sea shells by the sea shore";
+        let (tree, data) = coded(input);
+        let mut reader = BitReader::new(&data[..data.len() / 2]);
+        let items: Vec<_> = decode_iter(&mut reader, &tree, input.len() as u64).collect();
+
+        let (last, decoded) = items.split_last().unwrap();
+        assert!(matches!(last, Err(HuffmanError::UnexpectedEof)));
+        assert!(decoded.iter().all(|c| c.is_ok()));
+        assert!(decoded.len() < input.len());
+    }
+}
@@ -0,0 +1,415 @@
+//! Huffman coding of byte streams.
+//!
+//! Everything needs the `std` feature, which is on by default, except for building trees with
+//! `Tree::from_weights` and coding bytes into and out of buffers with `pack` and `unpack`. Without
+//! it the crate is `no_std`, and needs only `alloc`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+
+/// Only compile each of the items given with the `std` feature.
+macro_rules! with_std {
+    ($($item:item)*) => {
+        $(#[cfg(feature = "std")] $item)*
+    };
+}
+
+mod packed;
+#[cfg(test)]
+mod testing;
+mod tree;
+
+pub use packed::{PackError, pack, unpack};
+pub use tree::{TieBreak, Tree, Weight};
+
+with_std! {
+    use std::collections::HashMap;
+    use std::hash::Hash;
+    use std::io::{Read, Write, BufRead, BufReader, self};
+
+    mod adaptive;
+    mod bits;
+    mod config;
+    mod crc;
+    mod error;
+    mod escape;
+    mod file;
+    mod format;
+    mod freq;
+    mod iter;
+    mod limit;
+    mod lookup;
+    mod remap;
+    mod rle;
+    mod stats;
+
+    pub use adaptive::{AdaptiveDecoder, AdaptiveEncoder};
+    pub use bits::{BitOrder, BitReader, BitText, BitWriter};
+    pub use config::{EncoderConfig, compress_with_config};
+    pub use error::HuffmanError;
+    pub use escape::{Escaped, build_tree_with_escape, decode_escaped, encode_escaped};
+    pub use file::{CompressStats, DecompressStats, compress_file, compress_reader, decompress_file};
+    pub use format::{CODEBOOK_MAGIC, Header, INDEX_MAGIC, MAGIC, VERSION, compress, compress_with_table, compress_with_tree, decompress, decompress_with_table, decompress_with_tree};
+    pub use format::{predicted_header_size, predicted_size, read_codebook, write_codebook};
+    pub use format::{read_after_header, read_block_index, read_blocks, read_compressed, write_blocks, write_compressed, write_compressed_named, write_compressed_rle, write_compressed_with_checksum};
+    pub use freq::{count_frequencies, count_frequencies_many, count_tokens, count_tokens_capped, load_table, merge_frequencies, save_table};
+    #[cfg(feature = "parallel")]
+    pub use format::compress_blocks_parallel;
+    #[cfg(feature = "parallel")]
+    pub use freq::count_frequencies_parallel;
+    pub use iter::{DecodeIter, decode_iter};
+    pub use limit::{build_length_limited, build_tree_limited};
+    pub use lookup::DecodeTable;
+    pub use remap::{REMAP_MAX_LEN, SymbolMap};
+    pub use rle::{run_length_decode, run_length_encode};
+    pub use stats::{Analysis, INCOMPRESSIBLE_ENTROPY, SymbolCost, analyze, entropy, average_code_length, symbol_costs};
+    pub use tree::byte_label;
+    use tree::Tree::{Leaf, Node};
+
+    /// Build the Huffman tree for a map of symbol frequencies, or `None` if the map is empty.
+    pub fn build_tree<S: Clone + Ord>(freqs: HashMap<S, u64>) -> Option<Tree<S>> {
+        if freqs.is_empty() {
+            None
+        } else {
+            Some(Tree::from(freqs))
+        }
+    }
+
+    /// The `(code, depth)` that `tree` assigns to each symbol, with the root branch in the highest bit.
+    pub fn encoding_table<S: Clone + Eq + Hash>(tree: &Tree<S>) -> HashMap<S, (u64, usize)> {
+        tree.encode()
+    }
+
+    /// Write each byte of `input` as its code from `table`.
+    pub fn encode<W: Write>(input: &[u8], table: &HashMap<u8, (u64, usize)>, writer: &mut BitWriter<W>) -> Result<(), HuffmanError> {
+        encode_array(input, &stream_array(&table_array(table), writer.order()), writer)
+    }
+
+    /// Write each byte of `input` as its code from `codes`, which must already be in stream order.
+    /// Indexing an array is three to four times as fast as looking each byte up in a `HashMap`,
+    /// going by the `encode HashMap` stage of `cargo bench`.
+    pub(crate) fn encode_array<W: Write>(input: &[u8], codes: &[(u64, usize); 256], writer: &mut BitWriter<W>) -> Result<(), HuffmanError> {
+        for &c in input {
+            let (code, depth) = codes[c as usize];
+            if depth == 0 {
+                return Err(HuffmanError::MissingCode(c));
+            }
+            writer.write_bits(code, depth)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lay out a table from `encoding_table` as `Tree::encode_array` would.
+    fn table_array(table: &HashMap<u8, (u64, usize)>) -> [(u64, usize); 256] {
+        let mut array = [(0u64, 0usize); 256];
+        for (&c, &code) in table {
+            array[c as usize] = code;
+        }
+        array
+    }
+
+    /// Put each code of an array from `Tree::encode_array` into stream order.
+    pub(crate) fn stream_array(codes: &[(u64, usize); 256], order: BitOrder) -> [(u64, usize); 256] {
+        codes.map(|(code, depth)| (stream_order(code, depth, order), depth))
+    }
+
+    /// Encode everything read from `input` to `output` a chunk at a time, so that the whole input
+    /// never has to be held in memory. Returns the number of bytes read.
+    pub fn encode_stream<R: Read, W: Write>(input: R, output: W, table: &HashMap<u8, (u64, usize)>) -> Result<u64, HuffmanError> {
+        encode_stream_with_progress(input, output, table, |_| ())
+    }
+
+    /// Like `encode_stream`, but calling `progress` with the number of bytes encoded so far after
+    /// each chunk of input, and never in between, so it costs nothing per byte.
+    pub fn encode_stream_with_progress<R, W, F>(input: R, output: W, table: &HashMap<u8, (u64, usize)>, progress: F) -> Result<u64, HuffmanError>
+        where R: Read, W: Write, F: FnMut(u64)
+    {
+        let mut writer = BitWriter::new(output);
+        let consumed = encode_reader(input, &table_array(table), &mut writer, progress)?;
+        writer.finish()?;
+        Ok(consumed)
+    }
+
+    /// Encode everything read from `input` onto the end of `writer` with the codes from
+    /// `Tree::encode_array`, returning the number of bytes read. `progress` is told the running total
+    /// after each chunk.
+    pub(crate) fn encode_reader<R, W, F>(input: R, codes: &[(u64, usize); 256], writer: &mut BitWriter<W>, mut progress: F) -> Result<u64, HuffmanError>
+        where R: Read, W: Write, F: FnMut(u64)
+    {
+        let codes = stream_array(codes, writer.order());
+        let mut input = BufReader::with_capacity(1 << 16, input);
+        let mut consumed = 0u64;
+
+        loop {
+            let len = {
+                let chunk = match input.fill_buf() {
+                    Ok(chunk) => chunk,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if chunk.is_empty() {
+                    break;
+                }
+                encode_array(chunk, &codes, writer)?;
+                chunk.len()
+            };
+            input.consume(len);
+            consumed += len as u64;
+            progress(consumed);
+        }
+
+        Ok(consumed)
+    }
+
+    /// Decode `count` bytes from `reader`, walking `tree` from the root one bit at a time. Bits are
+    /// read only as the codes need them, so `reader` is left just after the last code, even mid-byte.
+    pub fn decode<R: Read, W: Write>(reader: &mut BitReader<R>, tree: &Tree, count: u64, mut output: W) -> Result<(), HuffmanError> {
+        for _ in 0..count {
+            let first = next_bit(reader)?;
+            output.write_all(&[walk(reader, tree, first)?])?;
+        }
+
+        Ok(())
+    }
+
+    /// Like `decode`, but into `out` rather than a writer, returning the number of bytes decoded.
+    /// Fails with `OutputTooSmall` before reading anything if `count` bytes wouldn't fit.
+    pub fn decode_into<R: Read>(reader: &mut BitReader<R>, tree: &Tree, count: u64, out: &mut [u8]) -> Result<usize, HuffmanError> {
+        if count > out.len() as u64 {
+            return Err(HuffmanError::OutputTooSmall { needed: count, capacity: out.len() });
+        }
+
+        let out = &mut out[..count as usize];
+        for byte in out.iter_mut() {
+            let first = next_bit(reader)?;
+            *byte = walk(reader, tree, first)?;
+        }
+        Ok(out.len())
+    }
+
+    /// Like `decode`, but if the stream ends partway, keep every byte up to the last complete code
+    /// rather than failing, for recovering what is left of a damaged stream. Returns the bytes
+    /// decoded and whether the stream was cut short of `count` bytes.
+    pub fn decode_lossy<R: Read>(reader: &mut BitReader<R>, tree: &Tree, count: u64) -> Result<(Vec<u8>, bool), HuffmanError> {
+        let mut output = Vec::new();
+        for _ in 0..count {
+            let decoded = next_bit(reader).and_then(|first| walk(reader, tree, first));
+            match decoded {
+                Ok(c) => output.push(c),
+                Err(HuffmanError::UnexpectedEof) => return Ok((output, true)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok((output, false))
+    }
+
+    /// Decode `count` bytes of `LsbFirst` codes straight from `data`, indexing its bits directly
+    /// rather than going through a `BitReader`. This is `unpack` with the crate's usual error type.
+    pub fn decode_slice(data: &[u8], tree: &Tree, count: u64, out: &mut Vec<u8>) -> Result<(), HuffmanError> {
+        unpack(data, tree, count, out)?;
+        Ok(())
+    }
+
+    /// Follow a code from the root of `tree` down to its leaf, given the first bit of the code.
+    pub(crate) fn walk<R: Read, S: Clone>(reader: &mut BitReader<R>, tree: &Tree<S>, first: bool) -> Result<S, HuffmanError> {
+        let mut node: &Tree<S> = match tree {
+            // A tree of one symbol codes it as a single 0 bit.
+            Leaf(c, _) => return Ok(c.clone()),
+            Node(l, r, _) => if first { r } else { l },
+        };
+
+        loop {
+            match node {
+                Leaf(c, _) => return Ok(c.clone()),
+                Node(l, r, _) => node = if next_bit(reader)? { r } else { l },
+            }
+        }
+    }
+
+    pub(crate) fn next_bit<R: Read>(reader: &mut BitReader<R>) -> Result<bool, HuffmanError> {
+        reader.read_bit()?.ok_or(HuffmanError::UnexpectedEof)
+    }
+
+    /// Codes from `Tree::encode` hold the branch taken at the root in their most significant bit,
+    /// but a `BitWriter` in `LsbFirst` order emits the least significant bit first. Reverse the code
+    /// in that case so that the branch bits land in the stream in the order a decoder walking down
+    /// from the root will read them.
+    pub(crate) fn stream_order(code: u64, depth: usize, order: BitOrder) -> u64 {
+        match order {
+            BitOrder::MsbFirst => code,
+            BitOrder::LsbFirst if depth == 0 => 0,
+            BitOrder::LsbFirst => code.reverse_bits() >> (64 - depth),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// The tree for `input`, and `input` coded with it.
+        fn coded(input: &[u8]) -> (Tree, Vec<u8>) {
+            let tree = build_tree(count_frequencies(input).unwrap()).unwrap();
+            let mut writer = BitWriter::new(Vec::new());
+            encode(input, &encoding_table(&tree), &mut writer).unwrap();
+            (tree, writer.finish().unwrap())
+        }
+
+        #[test]
+        fn empty_input_builds_no_tree() {
+            assert_eq!(build_tree(HashMap::<u8, u64>::new()), None);
+            assert_eq!(build_tree(count_frequencies(&b""[..]).unwrap()), None);
+        }
+
+        #[test]
+        fn decode_reverses_encode() {
+            let input = b"abracadabra, said the magician";
+            let (tree, data) = coded(input);
+            let mut output = Vec::new();
+            decode(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut output).unwrap();
+            assert_eq!(output, &input[..]);
+        }
+
+        #[test]
+        fn decode_fails_when_the_stream_ends_mid_code() {
+            let input = b"abracadabra, said the magician";
+            let (tree, data) = coded(input);
+            let mut output = Vec::new();
+            let result = decode(&mut BitReader::new(&data[..data.len() - 1]), &tree, input.len() as u64, &mut output);
+            match result {
+                Err(HuffmanError::UnexpectedEof) => {}
+                other => panic!("Expected UnexpectedEof, got {:?}", other),
+            }
+        }
+
+        /// Reads a few bytes at a time, in sizes that never line up with bytes of code.
+        struct Trickle<'a> {
+            data: &'a [u8],
+            reads: usize,
+        }
+
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.reads += 1;
+                let len = buf.len().min(self.data.len()).min(1 + self.reads % 7);
+                buf[..len].copy_from_slice(&self.data[..len]);
+                self.data = &self.data[len..];
+                Ok(len)
+            }
+        }
+
+        #[test]
+        fn streaming_in_awkward_chunks_matches_encoding_at_once() {
+            let input = b"the quick brown fox jumps over the lazy dog".repeat(20);
+            let (tree, whole) = coded(&input);
+            let mut streamed = Vec::new();
+            let consumed = encode_stream(Trickle { data: &input, reads: 0 }, &mut streamed, &encoding_table(&tree)).unwrap();
+            assert_eq!(consumed, input.len() as u64);
+            assert_eq!(streamed, whole);
+        }
+
+        #[test]
+        fn decoding_stops_mid_byte_at_the_last_code() {
+            let (first, second) = (b"abracadabra", b"the quick brown fox");
+            let first_tree = build_tree(count_frequencies(&first[..]).unwrap()).unwrap();
+            let second_tree = build_tree(count_frequencies(&second[..]).unwrap()).unwrap();
+
+            // Both blocks in one run of bits, the second starting partway through a byte.
+            let mut writer = BitWriter::new(Vec::new());
+            encode(first, &encoding_table(&first_tree), &mut writer).unwrap();
+            assert_ne!(writer.padding_bits(), 0);
+            encode(second, &encoding_table(&second_tree), &mut writer).unwrap();
+            let data = writer.finish().unwrap();
+
+            let mut reader = BitReader::new(&data[..]);
+            let mut output = Vec::new();
+            decode(&mut reader, &first_tree, first.len() as u64, &mut output).unwrap();
+            decode(&mut reader, &second_tree, second.len() as u64, &mut output).unwrap();
+            assert_eq!(output, [&first[..], &second[..]].concat());
+
+            let mut reader = BitReader::new(&data[..]);
+            let mut output = Vec::new();
+            DecodeTable::new(&first_tree, BitOrder::LsbFirst).decode(&mut reader, first.len() as u64, &mut output).unwrap();
+            DecodeTable::new(&second_tree, BitOrder::LsbFirst).decode(&mut reader, second.len() as u64, &mut output).unwrap();
+            assert_eq!(output, [&first[..], &second[..]].concat());
+        }
+
+        #[test]
+        fn decoding_a_slice_matches_decoding_a_reader() {
+            let input = b"she sells sea shells by the sea shore".repeat(10);
+            let (tree, data) = coded(&input);
+
+            let mut from_reader = Vec::new();
+            decode(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut from_reader).unwrap();
+            let mut from_slice = Vec::new();
+            decode_slice(&data, &tree, input.len() as u64, &mut from_slice).unwrap();
+            assert_eq!(from_slice, from_reader);
+            assert_eq!(from_slice, input);
+
+            let mut cut = Vec::new();
+            assert!(matches!(decode_slice(&data[..10], &tree, input.len() as u64, &mut cut), Err(HuffmanError::UnexpectedEof)));
+        }
+
+        #[test]
+        fn progress_reaches_the_input_size() {
+            let input = b"the quick brown fox jumps over the lazy dog".repeat(5000);
+            let (tree, whole) = coded(&input);
+            let mut reports = Vec::new();
+            let mut output = Vec::new();
+            encode_stream_with_progress(&input[..], &mut output, &encoding_table(&tree), |done| reports.push(done)).unwrap();
+
+            assert_eq!(output, whole);
+            assert_eq!(reports.last(), Some(&(input.len() as u64)));
+            assert!(reports.len() > 1 && reports.len() < input.len() / 1000);
+            assert!(reports.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        #[test]
+        fn a_cut_stream_decodes_up_to_its_last_whole_code() {
+            let input = b"the quick brown fox jumps over the lazy dog";
+            let (tree, data) = coded(input);
+            let table = encoding_table(&tree);
+            // Where each code ends, in bits from the start of the stream.
+            let ends: Vec<usize> = input.iter()
+                .scan(0, |end, c| {
+                    *end += table[c].1;
+                    Some(*end)
+                })
+                .collect();
+
+            for len in 0..=data.len() {
+                let (recovered, cut) = decode_lossy(&mut BitReader::new(&data[..len]), &tree, input.len() as u64).unwrap();
+                let whole = ends.iter().take_while(|&&end| end <= len * 8).count();
+                assert_eq!(recovered, &input[..whole]);
+                assert_eq!(cut, whole < input.len());
+            }
+        }
+
+        #[test]
+        fn decoding_into_a_buffer_needs_room_for_every_byte() {
+            let input = b"she sells sea shells";
+            let (tree, data) = coded(input);
+
+            let mut small = [0u8; 19];
+            match decode_into(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut small) {
+                Err(HuffmanError::OutputTooSmall { needed: 20, capacity: 19 }) => {}
+                other => panic!("Expected OutputTooSmall, got {:?}", other),
+            }
+            assert_eq!(small, [0u8; 19]);
+
+            let mut exact = [0u8; 20];
+            assert_eq!(decode_into(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut exact).unwrap(), 20);
+            assert_eq!(&exact, input);
+
+            // Anything past the decoded bytes is left alone.
+            let mut large = [0xffu8; 30];
+            assert_eq!(decode_into(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut large).unwrap(), 20);
+            assert_eq!(&large[..20], input);
+            assert_eq!(large[20..], [0xff; 10]);
+        }
+    }
+}
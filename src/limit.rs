@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+use error::HuffmanError;
+use tree::{Tree, canonical_codes};
+use tree::Tree::{Leaf, Node};
+
+/// Build canonical codes for `freqs` that are no longer than `max_len` bits, using the
+/// package-merge algorithm to keep the lengths optimal under that limit.
+///
+/// Fails if `max_len` is too short to give each symbol its own code, which needs at least
+/// `ceil(log2(n))` bits for `n` symbols.
+pub fn build_length_limited<S>(freqs: &HashMap<S, u64>, max_len: usize) -> Result<HashMap<S, (u64, usize)>, HuffmanError>
+    where S: Clone + Ord + Hash
+{
+    let mut leaves: Vec<_> = freqs.iter().map(|(c, &count)| (count, c.clone())).collect();
+    leaves.sort();
+
+    let n = leaves.len();
+    let min_len = match n {
+        0 => return Ok(HashMap::new()),
+        1 => 1,
+        _ => (usize::BITS - (n - 1).leading_zeros()) as usize,
+    };
+    if max_len < min_len {
+        return Err(HuffmanError::LengthLimitTooSmall { max_len, symbols: n, min_len });
+    }
+    if n == 1 {
+        return Ok(canonical_codes(leaves.into_iter().map(|(_, c)| (c, 1))));
+    }
+
+    let items: Vec<_> = leaves.iter()
+        .enumerate()
+        .map(|(i, &(count, _))| (count, Rc::new(Item::Leaf(i))))
+        .collect();
+
+    // No optimal code is ever longer than n - 1 bits.
+    let mut list = items.clone();
+    for _ in 1..max_len.min(n - 1) {
+        let packages = list.chunks_exact(2)
+            .map(|pair| (pair[0].0.saturating_add(pair[1].0), Rc::new(Item::Package(pair[0].1.clone(), pair[1].1.clone()))));
+        list = merge(items.iter().cloned(), packages);
+    }
+
+    let mut lengths = vec![0; n];
+    for (_, item) in &list[..2 * n - 2] {
+        item.count(&mut lengths);
+    }
+
+    Ok(canonical_codes(leaves.into_iter().map(|(_, c)| c).zip(lengths)))
+}
+
+/// Build the tree for the codes `build_length_limited` gives, so that no symbol is deeper than
+/// `max_depth`, or `None` if `freqs` is empty. Each leaf is weighted by its count, as in a tree
+/// from `Tree::from`.
+///
+/// A `max_depth` below `ceil(log2(n))` for `n` symbols can't be met, and fails with
+/// `DepthUnsatisfiable` giving that minimum, so that a caller can pick a depth that will work.
+pub fn build_tree_limited<S>(freqs: HashMap<S, u64>, max_depth: usize) -> Result<Option<Tree<S>>, HuffmanError>
+    where S: Clone + Ord + Hash
+{
+    if freqs.is_empty() {
+        return Ok(None);
+    }
+    let lengths = build_length_limited(&freqs, max_depth)
+        .map_err(|e| match e {
+            HuffmanError::LengthLimitTooSmall { min_len, .. } => HuffmanError::DepthUnsatisfiable { max_depth, min_depth: min_len },
+            e => e,
+        })?
+        .into_iter()
+        .map(|(c, (_, len))| (c, len))
+        .collect();
+    Ok(Some(with_counts(Tree::from_code_lengths(&lengths)?, &freqs)))
+}
+
+/// `tree` with each leaf weighted by its count in `freqs`, and each node by the sum below it.
+fn with_counts<S: Eq + Hash>(tree: Tree<S>, freqs: &HashMap<S, u64>) -> Tree<S> {
+    match tree {
+        Leaf(c, _) => {
+            let count = freqs[&c];
+            Leaf(c, count)
+        }
+        Node(l, r, _) => with_counts(*l, freqs) + with_counts(*r, freqs),
+    }
+}
+
+/// A coin in the package-merge algorithm: either a single symbol, or a package of two coins
+/// from the level below.
+enum Item {
+    Leaf(usize),
+    Package(Rc<Item>, Rc<Item>),
+}
+
+impl Item {
+    /// Add one to the code length of every symbol in this coin.
+    fn count(&self, lengths: &mut [usize]) {
+        match self {
+            Item::Leaf(i) => lengths[*i] += 1,
+            Item::Package(a, b) => {
+                a.count(lengths);
+                b.count(lengths);
+            }
+        }
+    }
+}
+
+/// Merge two lists of coins sorted by weight, taking from `leaves` first on ties.
+fn merge<L, P>(leaves: L, packages: P) -> Vec<(u64, Rc<Item>)>
+    where L: Iterator<Item = (u64, Rc<Item>)>, P: Iterator<Item = (u64, Rc<Item>)>
+{
+    let mut leaves = leaves.peekable();
+    let mut packages = packages.peekable();
+    let mut merged = Vec::new();
+    loop {
+        let take_leaf = match (leaves.peek(), packages.peek()) {
+            (Some(leaf), Some(package)) => leaf.0 <= package.0,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return merged,
+        };
+        merged.extend(if take_leaf { leaves.next() } else { packages.next() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twenty symbols weighted by the Fibonacci numbers, which unlimited make a tree 19 deep.
+    fn fibonacci() -> HashMap<u8, u64> {
+        let mut weights = (1u64, 1u64);
+        (0..20).map(|c| {
+            let weight = weights.0;
+            weights = (weights.1, weights.0 + weights.1);
+            (c, weight)
+        }).collect()
+    }
+
+    fn cost(freqs: &HashMap<u8, u64>, codes: &HashMap<u8, (u64, usize)>) -> u64 {
+        freqs.iter().map(|(c, &count)| count * codes[c].1 as u64).sum()
+    }
+
+    #[test]
+    fn fibonacci_codes_stay_within_the_limit() {
+        let freqs = fibonacci();
+        let unlimited = Tree::from(freqs.clone()).encode();
+        assert_eq!(unlimited.values().map(|&(_, len)| len).max(), Some(19));
+
+        for max_len in 5..=19 {
+            let codes = build_length_limited(&freqs, max_len).unwrap();
+            assert_eq!(codes.len(), freqs.len());
+            assert!(codes.values().all(|&(_, len)| len <= max_len));
+            // Still a complete prefix code.
+            assert_eq!(codes.values().map(|&(_, len)| 1u64 << (19 - len)).sum::<u64>(), 1 << 19);
+            // Near-optimal: limiting to 8 bits costs under 3% more.
+            if max_len >= 8 {
+                assert!(cost(&freqs, &codes) * 100 < cost(&freqs, &unlimited) * 103);
+            }
+        }
+        let codes = build_length_limited(&freqs, 19).unwrap();
+        assert_eq!(cost(&freqs, &codes), cost(&freqs, &unlimited));
+    }
+
+    #[test]
+    fn too_short_a_limit_is_an_error() {
+        match build_length_limited(&fibonacci(), 4) {
+            Err(HuffmanError::LengthLimitTooSmall { max_len: 4, symbols: 20, min_len: 5 }) => {}
+            other => panic!("Expected LengthLimitTooSmall, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn an_achievable_depth_limits_the_tree() {
+        let freqs = fibonacci();
+        for max_depth in 5..=19 {
+            let tree = build_tree_limited(freqs.clone(), max_depth).unwrap().unwrap();
+            assert!(tree.depth() <= max_depth);
+            assert_eq!(tree.leaf_count(), 20);
+            let codes = build_length_limited(&freqs, max_depth).unwrap();
+            assert_eq!(tree.encode(), codes);
+            // Weighted by the counts, so that the tree knows how many bits it codes them in.
+            assert_eq!(tree.weighted_path_length(), freqs.iter().map(|(c, &count)| u128::from(count) * codes[c].1 as u128).sum::<u128>());
+        }
+
+        // Exactly the minimum, and nothing to build at all.
+        let all_bytes: HashMap<u8, u64> = (0..=255).map(|c| (c, 1 + u64::from(c))).collect();
+        assert_eq!(build_tree_limited(all_bytes, 8).unwrap().unwrap().depth(), 8);
+        assert!(build_tree_limited(HashMap::<u8, u64>::new(), 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn an_impossible_depth_names_the_minimum() {
+        let freqs: HashMap<u16, u64> = (0..300).map(|c| (c, 1)).collect();
+        match build_tree_limited(freqs, 8) {
+            Err(e @ HuffmanError::DepthUnsatisfiable { max_depth: 8, min_depth: 9 }) => {
+                assert_eq!(e.to_string(), "A tree of depth 8 is too shallow for every symbol; it must be at least 9 deep");
+            }
+            other => panic!("Expected DepthUnsatisfiable, got {:?}", other),
+        }
+    }
+}
@@ -1,164 +1,678 @@
-use std::collections::{HashMap, BinaryHeap};
-use std::io::{Read, Write, BufReader, stdin, self};
+extern crate rust_huffman;
 
-fn main() -> Result<(), io::Error> {
-    let map = parse()?;
+use std::collections::HashMap;
+use std::env;
+use std::hash::Hash;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write, BufRead, BufReader, BufWriter, stdin, stdout, self};
+use std::process;
 
-    #[cfg(not)]
-    println!("Map: {:#?}", map);
 
-    let tree = Tree::from(map);
+const USAGE: &str = "\
+usage: rust-huffman [--table | --encode | --decode | --verify | --analyze | --dot] [-i|--input <path>] [-o|--output <path>]
+                    [--table-file <path>] [--word-size 1|2|4] [--rle] [--block-size <bytes>] [--stats-json]
+                    [--freq <symbol>=<count>,...] [--warn-entropy <bits>] [--codebook <path>]
+                    [--checksum] [--raw-bits] [--keep-name] [--spool-above <bytes>]
 
-    #[cfg(not)]
-    println!("Tree: {:#?}", tree);
+    --table          print the code assigned to each byte of the input (default)
+    --encode         compress the input
+    --decode         decompress input produced by --encode
+    --verify         compress and decompress the input in memory, and check it comes back unchanged
+    --analyze        summarise how the bytes of the input are distributed, without building codes
+    --dot            print the tree built for the input in Graphviz DOT format
+    -i, --input      read from <path> instead of stdin
+    -o, --output     write to <path> instead of stdout
+    --table-file     load byte frequencies from <path> instead of counting the input; with --encode
+                     and --decode, the data is coded with them and no tree is stored
+    --word-size      with --table, code the input as little-endian words of this many bytes
+    --rle            with --encode, replace runs of repeated bytes with their lengths before coding
+    --block-size     with --encode, code the input in blocks of this many bytes, each with its own tree
+    --stats-json     with --encode and --output, print statistics about the compression to stdout as JSON
+    --freq           with --table, --analyze or --dot, use these frequencies instead of reading any input,
+                     such as 'A=45,B=13,C=12'; each symbol is one ASCII character or a 0x-prefixed byte
+    --warn-entropy   with --encode, warn that the input looks incompressible if its entropy is at least
+                     this many bits per byte (default 7.95); not with --table-file or --block-size
+    --codebook       with --encode, write the tree to <path> and leave the header and tree out of the
+                     output; with --decode, decode such output with the tree from <path>
+    --checksum       with --encode, store a CRC-32 of the input, which --decode checks
+    --raw-bits       with --encode, print the coded data as 0s and 1s in the order they are written,
+                     a byte at a time, leaving out the header and tree
+    --keep-name      with --encode and --input, keep the input's file name in the output; with --decode
+                     and no --output, write to a new file in the current directory with the kept name
+    --spool-above    with --encode, hold at most this many bytes of the input in memory, and copy a longer
+                     input to a temporary file to read it a second time";
 
-    let mut encode: Vec<_> = tree.encode().into_iter().collect();
-    encode.sort_by(|(_, (l_code, l_depth)), (_, (r_code, r_depth))| {
-        if l_depth < r_depth {
-            (l_code << (r_depth - l_depth)).cmp(r_code)
-        } else {
-            (l_code).cmp(&(r_code << (l_depth - r_depth)))
+fn main() {
+    match run() {
+        Ok(()) => {}
+        // Whatever was reading the output has stopped, as `head` does, so there's no one left
+        // to tell and nothing wrong with the input.
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("rust-huffman: {}", e);
+            process::exit(1);
         }
-    });
-
-    println!("Encoding");
-    println!("========");
-    for (c, (code, depth)) in encode {
-        println!(
-            "{0:4} => {1:>#02$b}",
-            format!("{:?}", std::char::from_u32(c as u32).expect("Invalid ASCII character")),
-            code, depth + 2
-        );
     }
-
-    Ok(())
 }
 
-fn parse() -> Result<HashMap<u8, u64>, io::Error> {
-    let mut map = HashMap::new();
+fn run() -> Result<(), io::Error> {
+    let options = Options::parse(env::args().skip(1))?;
+    let output = options.open_output()?;
 
-    let stdin = BufReader::with_capacity(1 << 16, stdin());
-    for c in stdin.bytes() {
-        let c = c?;
-        let seen = map.remove(&c).unwrap_or(0);
-        map.insert(c, seen + 1);
+    match options.mode {
+        Mode::Table => match options.word_size {
+            2 => table(count_words(options.open_input()?, 2)?, |&w| format!("{:#06x}", w), None, output),
+            4 => table(count_words(options.open_input()?, 4)?, |&w| format!("{:#010x}", w), None, output),
+            _ => {
+                let map = options.frequencies()?;
+                let legend = byte_legend(map.keys().cloned());
+                if legend.is_some() {
+                    eprintln!("rust-huffman: warning: the input has bytes that aren't printable ASCII, marked in the table");
+                }
+                table(map, |&c| table_label(c), legend, output)
+            }
+        },
+        Mode::Encode => match (options.block_size, options.spool_above) {
+            (Some(size), _) => blocks(options.open_input()?, output, size),
+            (None, Some(threshold)) => spool(options.open_input()?, output, threshold),
+            (None, None) if options.raw_bits => raw_bits(options.open_input()?, output),
+            (None, None) if options.codebook.is_some() => encode_with_codebook(options.open_input()?, output, options.create_codebook()?),
+            (None, None) if options.stats_json => encode_with_stats(options.open_input()?, output, options.warn_entropy),
+            (None, None) => encode(options.open_input()?, output, options.table_file()?, &options.encoder_config(), options.warn_entropy),
+        },
+        Mode::Decode => match options.codebook {
+            Some(_) => decode_with_codebook(options.open_input()?, output, options.open_codebook()?),
+            None if options.keep_name => decode_named(options.open_input()?),
+            None => decode(options.open_input()?, output, options.table_file()?),
+        },
+        Mode::Verify => verify(options.open_input()?, output),
+        Mode::Analyze => analyze(options.frequencies()?, output),
+        Mode::Dot => dot(options.frequencies()?, output),
     }
+}
 
-    Ok(map)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Table,
+    Encode,
+    Decode,
+    Verify,
+    Analyze,
+    Dot,
 }
 
-#[derive(Debug, Ord, Eq, PartialEq)]
-enum Tree {
-    Leaf(u8, u64),
-    Node(Box<Tree>, Box<Tree>, u64),
+#[derive(Debug)]
+struct Options {
+    mode: Mode,
+    input: Option<String>,
+    output: Option<String>,
+    table_file: Option<String>,
+    word_size: usize,
+    rle: bool,
+    block_size: Option<usize>,
+    stats_json: bool,
+    freq: Option<HashMap<u8, u64>>,
+    warn_entropy: f64,
+    codebook: Option<String>,
+    checksum: bool,
+    raw_bits: bool,
+    keep_name: bool,
+    spool_above: Option<u64>,
 }
-use self::Tree::*;
 
-impl Tree {
-    fn prob(&self) -> u64 {
-        match self {
-            Leaf(_, p) => *p,
-            Node(_, _, p) => *p,
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, io::Error> {
+        let mut mode = None;
+        let mut input = None;
+        let mut output = None;
+        let mut table_file = None;
+        let mut word_size = 1;
+        let mut rle = false;
+        let mut block_size = None;
+        let mut stats_json = false;
+        let mut freq = None;
+        let mut warn_entropy = None;
+        let mut codebook = None;
+        let mut checksum = false;
+        let mut raw_bits = false;
+        let mut keep_name = false;
+        let mut spool_above = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--table" => set_mode(&mut mode, Mode::Table)?,
+                "--encode" => set_mode(&mut mode, Mode::Encode)?,
+                "--decode" => set_mode(&mut mode, Mode::Decode)?,
+                "--verify" => set_mode(&mut mode, Mode::Verify)?,
+                "--analyze" => set_mode(&mut mode, Mode::Analyze)?,
+                "--dot" => set_mode(&mut mode, Mode::Dot)?,
+                "-i" | "--input" => input = Some(value(&arg, &mut args)?),
+                "-o" | "--output" => output = Some(value(&arg, &mut args)?),
+                "--table-file" => table_file = Some(value(&arg, &mut args)?),
+                "--word-size" => word_size = match value(&arg, &mut args)?.as_str() {
+                    "1" => 1,
+                    "2" => 2,
+                    "4" => 4,
+                    size => return Err(usage_error(format!("word size must be 1, 2 or 4, not '{}'", size))),
+                },
+                "--rle" => rle = true,
+                "--block-size" => block_size = match value(&arg, &mut args)?.parse() {
+                    Ok(size) if size > 0 => Some(size),
+                    _ => return Err(usage_error("--block-size must be a whole number of bytes above 0".to_string())),
+                },
+                "--stats-json" => stats_json = true,
+                "--freq" => freq = Some(parse_freq(&value(&arg, &mut args)?)?),
+                "--codebook" => codebook = Some(value(&arg, &mut args)?),
+                "--checksum" => checksum = true,
+                "--raw-bits" => raw_bits = true,
+                "--keep-name" => keep_name = true,
+                "--spool-above" => spool_above = match value(&arg, &mut args)?.parse() {
+                    Ok(size) => Some(size),
+                    _ => return Err(usage_error("--spool-above must be a whole number of bytes".to_string())),
+                },
+                "--warn-entropy" => warn_entropy = match value(&arg, &mut args)?.parse() {
+                    Ok(bits) if (0.0..=8.0).contains(&bits) => Some(bits),
+                    _ => return Err(usage_error("--warn-entropy must be a number of bits from 0 to 8".to_string())),
+                },
+                "-h" | "--help" => {
+                    let _ = writeln!(stdout(), "{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(usage_error(format!("unknown argument '{}'", arg))),
+            }
+        }
+
+        let mode = mode.unwrap_or(Mode::Table);
+        if table_file.is_some() && ![Mode::Table, Mode::Encode, Mode::Decode].contains(&mode) {
+            return Err(usage_error("--table-file can only be used with --table, --encode or --decode".to_string()));
+        }
+        if word_size != 1 && (mode != Mode::Table || table_file.is_some()) {
+            return Err(usage_error("--word-size can only be used with --table, and not with --table-file".to_string()));
+        }
+
+        if rle && (mode != Mode::Encode || table_file.is_some()) {
+            return Err(usage_error("--rle can only be used with --encode, and not with --table-file".to_string()));
+        }
+
+        if block_size.is_some() && (mode != Mode::Encode || table_file.is_some() || rle) {
+            return Err(usage_error("--block-size can only be used with --encode, and not with --table-file or --rle".to_string()));
+        }
+
+        // The statistics go to stdout, so the compressed data has to go somewhere else.
+        if stats_json && (mode != Mode::Encode || output.is_none() || table_file.is_some() || rle || block_size.is_some()) {
+            return Err(usage_error("--stats-json can only be used with --encode and --output, and not with --table-file, --rle or --block-size".to_string()));
+        }
+
+        if freq.is_some() && (![Mode::Table, Mode::Analyze, Mode::Dot].contains(&mode) || input.is_some() || table_file.is_some() || word_size != 1) {
+            return Err(usage_error("--freq can only be used with --table, --analyze or --dot, and not with --input, --table-file or --word-size".to_string()));
+        }
+
+        if spool_above.is_some() && (mode != Mode::Encode || table_file.is_some() || rle || block_size.is_some() || stats_json
+            || warn_entropy.is_some() || codebook.is_some() || checksum || raw_bits || keep_name)
+        {
+            return Err(usage_error("--spool-above can only be used with --encode, and not with --table-file, --rle, --block-size, --stats-json, --warn-entropy, --codebook, --checksum, --raw-bits or --keep-name".to_string()));
+        }
+
+        if warn_entropy.is_some() && (mode != Mode::Encode || table_file.is_some() || block_size.is_some()) {
+            return Err(usage_error("--warn-entropy can only be used with --encode, and not with --table-file or --block-size".to_string()));
+        }
+        let warn_entropy = warn_entropy.unwrap_or(rust_huffman::INCOMPRESSIBLE_ENTROPY);
+
+        if codebook.is_some() && (![Mode::Encode, Mode::Decode].contains(&mode) || table_file.is_some() || rle || block_size.is_some() || stats_json) {
+            return Err(usage_error("--codebook can only be used with --encode or --decode, and not with --table-file, --rle, --block-size or --stats-json".to_string()));
+        }
+
+        if checksum && (mode != Mode::Encode || table_file.is_some() || block_size.is_some() || stats_json || codebook.is_some()) {
+            return Err(usage_error("--checksum can only be used with --encode, and not with --table-file, --block-size, --stats-json or --codebook".to_string()));
         }
+
+        if raw_bits && (mode != Mode::Encode || table_file.is_some() || rle || block_size.is_some() || stats_json || codebook.is_some() || checksum) {
+            return Err(usage_error("--raw-bits can only be used with --encode, and not with --table-file, --rle, --block-size, --stats-json, --codebook or --checksum".to_string()));
+        }
+
+        let keep_name_ok = match mode {
+            Mode::Encode => input.is_some() && table_file.is_none() && block_size.is_none() && !stats_json && codebook.is_none() && !raw_bits,
+            Mode::Decode => output.is_none() && table_file.is_none() && codebook.is_none(),
+            _ => false,
+        };
+        if keep_name && !keep_name_ok {
+            return Err(usage_error("--keep-name can only be used with --encode and --input, or with --decode and no --output, and not with --table-file, --block-size, --stats-json, --codebook or --raw-bits".to_string()));
+        }
+
+        Ok(Options { mode, input, output, table_file, word_size, rle, block_size, stats_json, freq, warn_entropy, codebook, checksum, raw_bits, keep_name, spool_above })
     }
 
-    fn encode(&self) -> HashMap<u8, (u64, usize)> {
-        fn recurse(node: &Tree, map: &mut HashMap<u8, (u64, usize)>, prefix: u64, depth: usize) {
-            match node {
-                Leaf(c, _) => {
-                    map.insert(*c, (prefix, depth));
-                }
-                Node(l, r, _) => {
-                    recurse(&l, map, (prefix << 1) | 0, depth + 1);
-                    recurse(&r, map, (prefix << 1) | 1, depth + 1);
-                }
+    /// How `--encode` should compress, from `--rle`, `--checksum` and `--keep-name`.
+    fn encoder_config(&self) -> rust_huffman::EncoderConfig {
+        let mut config = rust_huffman::EncoderConfig::new().run_length(self.rle).checksum(self.checksum);
+        if let (true, Some(path)) = (self.keep_name, self.input.as_ref()) {
+            config = config.name(path.as_str());
+        }
+        config
+    }
+
+    fn open_input(&self) -> Result<Box<dyn Read>, io::Error> {
+        match self.input {
+            Some(ref path) => {
+                let file = File::open(path).map_err(|e| path_error(path, e))?;
+                Ok(Box::new(file))
             }
+            None => Ok(Box::new(stdin())),
+        }
+    }
+
+    /// Use the frequencies from `--freq` or the table file if either was given, or else count
+    /// the input.
+    fn frequencies(&self) -> Result<HashMap<u8, u64>, io::Error> {
+        if let Some(ref map) = self.freq {
+            return Ok(map.clone());
         }
+        match self.table_file()? {
+            Some(map) => Ok(map),
+            None => Ok(rust_huffman::count_frequencies(self.open_input()?)?),
+        }
+    }
 
-        let mut map = HashMap::new();
-        recurse(self, &mut map, 0, 0);
-        map
+    fn table_file(&self) -> Result<Option<HashMap<u8, u64>>, io::Error> {
+        match self.table_file {
+            Some(ref path) => {
+                let file = File::open(path).map_err(|e| path_error(path, e))?;
+                let map = rust_huffman::load_table(file).map_err(|e| path_error(path, e.into()))?;
+                Ok(Some(map))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn create_codebook(&self) -> Result<(String, File), io::Error> {
+        let path = self.codebook.clone().expect("No codebook was given");
+        let file = File::create(&path).map_err(|e| path_error(&path, e))?;
+        Ok((path, file))
+    }
+
+    fn open_codebook(&self) -> Result<rust_huffman::Tree, io::Error> {
+        let path = self.codebook.as_ref().expect("No codebook was given");
+        let file = File::open(path).map_err(|e| path_error(path, e))?;
+        rust_huffman::read_codebook(BufReader::new(file)).map_err(|e| path_error(path, e.into()))
+    }
+
+    fn open_output(&self) -> Result<Box<dyn Write>, io::Error> {
+        match self.output {
+            Some(ref path) => {
+                let file = File::create(path).map_err(|e| path_error(path, e))?;
+                Ok(Box::new(BufWriter::new(file)))
+            }
+            None => Ok(Box::new(BufWriter::new(stdout()))),
+        }
     }
 }
 
-impl std::ops::Add for Tree {
-    type Output = Self;
+fn set_mode(mode: &mut Option<Mode>, new: Mode) -> Result<(), io::Error> {
+    match *mode {
+        Some(old) if old != new => Err(usage_error("only one of --table, --encode, --decode, --verify, --analyze and --dot may be given".to_string())),
+        _ => {
+            *mode = Some(new);
+            Ok(())
+        }
+    }
+}
+
+fn value<I: Iterator<Item = String>>(flag: &str, args: &mut I) -> Result<String, io::Error> {
+    args.next().ok_or_else(|| usage_error(format!("'{}' needs a value", flag)))
+}
+
+/// Parse frequencies given as `<symbol>=<count>` pairs separated by commas.
+fn parse_freq(spec: &str) -> Result<HashMap<u8, u64>, io::Error> {
+    let mut map = HashMap::new();
+    for pair in spec.split(',') {
+        let error = |message: String| usage_error(format!("--freq: {}", message));
+        // Split at the last '=', so that '=' can itself be given a count.
+        let (symbol, count) = pair.rsplit_once('=')
+            .ok_or_else(|| error(format!("expected '<symbol>=<count>', found '{}'", pair)))?;
+
+        let symbol = match (symbol.strip_prefix("0x"), symbol.as_bytes()) {
+            (Some(hex), _) => u8::from_str_radix(hex, 16).ok(),
+            (None, &[c]) => Some(c),
+            _ => None,
+        }.ok_or_else(|| error(format!("'{}' is not a single ASCII character or a 0x-prefixed byte", symbol)))?;
+        let count = count.parse()
+            .map_err(|_| error(format!("'{}' is not a count", count)))?;
 
-    fn add(self: Tree, right: Tree) -> Tree {
-        let total_prob = self.prob() + right.prob();
-        Node(Box::new(self), Box::new(right), total_prob)
+        if map.insert(symbol, count).is_some() {
+            return Err(error(format!("{} is given twice", rust_huffman::byte_label(symbol))));
+        }
     }
+
+    Ok(map)
+}
+
+fn usage_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n\n{}", message, USAGE))
 }
 
-impl std::cmp::PartialOrd for Tree {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(other.prob().cmp(&self.prob()))
+fn path_error(path: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path, e))
+}
+
+/// Count the little-endian words of `size` bytes that make up `input`.
+fn count_words(input: Box<dyn Read>, size: usize) -> Result<HashMap<u32, u64>, io::Error> {
+    let mut word = [0u8; 4];
+    let mut held = 0;
+    let map = rust_huffman::count_tokens(input, |c| {
+        word[held] = c;
+        held = (held + 1) % size;
+        if held == 0 { Some(u32::from_le_bytes(word)) } else { None }
+    })?;
+
+    if held > 0 {
+        let offset = map.values().sum::<u64>() * size as u64;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("input ends with a partial {}-byte word at byte {}", size, offset),
+        ));
     }
+    Ok(map)
 }
 
-impl From<HashMap<u8, u64>> for Tree {
-    fn from(probs: HashMap<u8, u64>) -> Tree {
-        let mut queue: BinaryHeap<_> = probs.into_iter()
-            .map(|(c, count)| Leaf(c, count))
-            .collect();
+fn table<S, F>(map: HashMap<S, u64>, label: F, legend: Option<String>, mut output: Box<dyn Write>) -> Result<(), io::Error>
+    where S: Clone + Ord + Hash, F: Fn(&S) -> String
+{
+    #[cfg(any())]
+    println!("Map: {:#?}", map);
+
+    let tree = rust_huffman::build_tree(map.clone());
+
+    #[cfg(any())]
+    if let Some(ref tree) = tree {
+        print!("{}", tree.draw_with(&label));
+    }
 
-        while queue.len() > 1 {
-            let first = queue.pop().unwrap();
-            let second = queue.pop().unwrap();
-            queue.push(first + second)
+    let codes: Vec<_> = tree.iter().flat_map(|tree| tree.iter_codes()).collect();
+    let table = tree.as_ref().map(rust_huffman::encoding_table).unwrap_or_default();
+
+    write_table(&codes, &label, &mut output)?;
+    if let Some(legend) = legend {
+        writeln!(output)?;
+        writeln!(output, "{}", legend)?;
+    }
+    if !table.is_empty() {
+        write_efficiency(&map, &table, &mut output)?;
+    }
+    if let Some(ref tree) = tree {
+        write_longest(tree, &label, &mut output)?;
+    }
+    if !table.is_empty() {
+        write_costs(&map, &table, &label, &mut output)?;
+    }
+    output.flush()
+}
+
+fn encode(mut input: Box<dyn Read>, mut output: Box<dyn Write>, table: Option<HashMap<u8, u64>>, config: &rust_huffman::EncoderConfig, warn_entropy: f64) -> Result<(), io::Error> {
+    match table {
+        Some(table) => {
+            rust_huffman::compress_with_table(input, &mut output, &table)?;
+        }
+        None => {
+            let mut buffer = Vec::new();
+            input.read_to_end(&mut buffer)?;
+            warn_if_incompressible(&rust_huffman::analyze(&rust_huffman::count_frequencies(&buffer[..])?), warn_entropy);
+            rust_huffman::compress_with_config(&buffer, &mut output, config)?;
         }
+    }
+    output.flush()
+}
+
+/// Compress the input as `encode` does, then print what happened as a JSON object.
+fn encode_with_stats(mut input: Box<dyn Read>, mut output: Box<dyn Write>, warn_entropy: f64) -> Result<(), io::Error> {
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer)?;
+    let mut compressed = Vec::new();
+    rust_huffman::write_compressed(&buffer, &mut compressed)?;
+    output.write_all(&compressed)?;
+    output.flush()?;
+
+    let map = rust_huffman::count_frequencies(&buffer[..])?;
+    let analysis = rust_huffman::analyze(&map);
+    let incompressible = warn_if_incompressible(&analysis, warn_entropy);
+    let max_depth = rust_huffman::build_tree(map.clone())
+        .and_then(|tree| tree.encode().values().map(|&(_, depth)| depth).max())
+        .unwrap_or(0);
+    let mut stdout = stdout();
+    writeln!(
+        stdout,
+        "{{\"input_size\": {}, \"output_size\": {}, \"ratio\": {}, \"entropy\": {}, \"distinct_symbols\": {}, \"max_code_depth\": {}, \"incompressible\": {}}}",
+        buffer.len(),
+        compressed.len(),
+        compressed.len() as f64 / buffer.len().max(1) as f64,
+        analysis.entropy,
+        analysis.distinct,
+        max_depth,
+        incompressible
+    )?;
+    stdout.flush()
+}
 
-        queue.pop().expect("At least one character")
+/// Warn on stderr if the input's entropy is at least `threshold` bits per byte, returning whether
+/// it was.
+fn warn_if_incompressible(analysis: &rust_huffman::Analysis, threshold: f64) -> bool {
+    let incompressible = analysis.incompressible(threshold);
+    if incompressible {
+        eprintln!(
+            "rust-huffman: warning: the input's entropy is {:.3} bits per byte, so it is likely already compressed \
+             and the output will be no smaller",
+            analysis.entropy
+        );
     }
+    incompressible
 }
 
-/// Write individual bits to a file. Least significant bits first.
-struct BitWriter<W: Write> {
-    buffer: u8,
-    buffer_len: usize,
-    inner: W,
+/// Compress the input with a tree built for it, writing the tree to the codebook rather than
+/// ahead of the data.
+fn encode_with_codebook(mut input: Box<dyn Read>, mut output: Box<dyn Write>, (path, codebook): (String, File)) -> Result<(), io::Error> {
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer)?;
+    let tree = rust_huffman::build_tree(rust_huffman::count_frequencies(&buffer[..])?)
+        .ok_or(rust_huffman::HuffmanError::EmptyInput)?;
+
+    let mut codebook = BufWriter::new(codebook);
+    rust_huffman::write_codebook(&tree, &mut codebook)
+        .and_then(|()| codebook.flush())
+        .map_err(|e| path_error(&path, e))?;
+    rust_huffman::compress_with_tree(&buffer[..], &mut output, &tree)?;
+    output.flush()
 }
 
-impl<W: Write> BitWriter<W> {
-    const BYTE_BITS: usize = 8;
+/// Code the input as `encode` does, but print the coded bits as text. The last byte is padded
+/// with zeros, as it would be in a stream.
+fn raw_bits(mut input: Box<dyn Read>, mut output: Box<dyn Write>) -> Result<(), io::Error> {
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer)?;
 
-    fn new(inner: W) -> BitWriter<W> {
-        BitWriter { buffer: 0u8, buffer_len: 0usize, inner }
+    if let Some(tree) = rust_huffman::build_tree(rust_huffman::count_frequencies(&buffer[..])?) {
+        let mut writer = rust_huffman::BitWriter::new(rust_huffman::BitText::new(&mut output, rust_huffman::BitOrder::LsbFirst));
+        rust_huffman::encode(&buffer[..], &rust_huffman::encoding_table(&tree), &mut writer)?;
+        writer.finish()?;
     }
+    writeln!(output)?;
+    output.flush()
+}
 
-    fn write_bits(&mut self, bits: u64, length: usize) -> Result<(), io::Error> {
-        let mut pair = (bits, length);
-        while pair.1 > 0usize {
-            pair = self.consume_bits(pair);
-            self.flush_byte()?;
+/// Decode a stream encoded with `--keep-name` into a file named as it says. An existing file is never
+/// replaced.
+fn decode_named(input: Box<dyn Read>) -> Result<(), io::Error> {
+    let mut input = BufReader::with_capacity(1 << 16, input);
+    let header = rust_huffman::Header::read(&mut input)?;
+    let name = header.name.clone()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "input has no file name; it wasn't encoded with --keep-name"))?;
+
+    let file = OpenOptions::new().write(true).create_new(true).open(&name).map_err(|e| path_error(&name, e))?;
+    let mut output = BufWriter::new(file);
+    rust_huffman::read_after_header(&header, input, &mut output)?;
+    output.flush()
+}
+
+fn decode_with_codebook(input: Box<dyn Read>, mut output: Box<dyn Write>, tree: rust_huffman::Tree) -> Result<(), io::Error> {
+    let input = BufReader::with_capacity(1 << 16, input);
+    rust_huffman::decompress_with_tree(input, &mut output, &tree)?;
+    output.flush()
+}
+
+fn spool(input: Box<dyn Read>, mut output: Box<dyn Write>, threshold: u64) -> Result<(), io::Error> {
+    rust_huffman::compress_reader(input, &mut output, threshold)?;
+    output.flush()
+}
+
+fn blocks(input: Box<dyn Read>, mut output: Box<dyn Write>, block_size: usize) -> Result<(), io::Error> {
+    rust_huffman::write_blocks(input, &mut output, block_size)?;
+    output.flush()
+}
+
+fn decode(input: Box<dyn Read>, mut output: Box<dyn Write>, table: Option<HashMap<u8, u64>>) -> Result<(), io::Error> {
+    let mut input = BufReader::with_capacity(1 << 16, input);
+    if table.is_none() {
+        let start = input.fill_buf()?;
+        if !start.is_empty() && !start.starts_with(&rust_huffman::MAGIC[..start.len().min(4)]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "input has no header; if it was encoded with --table-file or --codebook, decode it with the same option",
+            ));
         }
+    }
+    match table {
+        Some(table) => rust_huffman::decompress_with_table(input, &mut output, &table)?,
+        None => rust_huffman::read_blocks(input, &mut output)?,
+    };
+    output.flush()
+}
+
+fn verify(mut input: Box<dyn Read>, mut output: Box<dyn Write>) -> Result<(), io::Error> {
+    let mut original = Vec::new();
+    input.read_to_end(&mut original)?;
 
-        Ok(())
+    let mut compressed = Vec::new();
+    rust_huffman::write_compressed(&original, &mut compressed)?;
+    let mut decompressed = Vec::new();
+    rust_huffman::read_compressed(&compressed[..], &mut decompressed)?;
+
+    if decompressed != original {
+        let offset = original.iter().zip(&decompressed)
+            .position(|(l, r)| l != r)
+            .unwrap_or_else(|| original.len().min(decompressed.len()));
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("round trip differs from the input at byte {}", offset)));
     }
 
-    fn flush_byte(&mut self) -> Result <(), io::Error> {
-        if self.buffer_len == Self::BYTE_BITS {
-            let byte = [self.buffer];
-            self.inner.write(&byte)?;
-            self.buffer_len = 0;
-        }
+    writeln!(
+        output,
+        "OK: {} bytes compressed to {} (ratio {:.3})",
+        original.len(), compressed.len(), compressed.len() as f64 / original.len().max(1) as f64
+    )?;
+    output.flush()
+}
+
+fn analyze(map: HashMap<u8, u64>, mut output: Box<dyn Write>) -> Result<(), io::Error> {
+    let analysis = rust_huffman::analyze(&map);
 
-        Ok(())
+    writeln!(output, "total bytes:      {}", analysis.total)?;
+    writeln!(output, "distinct symbols: {}", analysis.distinct)?;
+    writeln!(output, "min frequency:    {}", analysis.min)?;
+    writeln!(output, "max frequency:    {}", analysis.max)?;
+    writeln!(output, "mean frequency:   {:.2}", analysis.mean)?;
+    writeln!(output, "entropy:          {:.4} bits/symbol", analysis.entropy)?;
+    writeln!(output, "optimal size:     {:.0} bytes", analysis.optimal_size().ceil())?;
+    output.flush()
+}
+
+fn dot(map: HashMap<u8, u64>, mut output: Box<dyn Write>) -> Result<(), io::Error> {
+    match rust_huffman::build_tree(map) {
+        Some(tree) => write!(output, "{}", tree.to_dot())?,
+        None => writeln!(output, "digraph huffman {{\n}}")?,
     }
+    output.flush()
+}
 
-    fn consume_bits(&mut self, (bits, length): (u64, usize)) -> (u64, usize) {
-        let to_consume = Self::BYTE_BITS.saturating_sub(self.buffer_len).min(length);
-        self.buffer = self.buffer.overflowing_shl(to_consume as u32).0;
-        self.buffer |= (bits as u8) & ((1 << to_consume) - 1);
-        (bits.overflowing_shr(to_consume as u32).0, length - to_consume)
+/// Like `byte_label`, but marking bytes that aren't printable as well as showing them in hex:
+/// control bytes as `<0x1B>`, delete as `<DEL>` and bytes above ASCII as `<0xE9>`.
+fn table_label(c: u8) -> String {
+    match c {
+        0x7f => "<DEL>".to_string(),
+        c if c == b' ' || c.is_ascii_graphic() => rust_huffman::byte_label(c),
+        c => format!("<0x{:02X}>", c),
     }
 }
 
-impl<W: Write> Drop for BitWriter<W> {
-    fn drop(&mut self) {
-        if self.buffer_len > 0 {
-            let byte = [self.buffer];
-            self.inner.write(&byte).expect("Flush final byte");
+/// A note on the labels `table_label` gives to any of `symbols` that aren't printable ASCII, or
+/// `None` if they all are.
+fn byte_legend<I: Iterator<Item = u8>>(symbols: I) -> Option<String> {
+    let (mut control, mut delete, mut high) = (false, false, false);
+    for c in symbols {
+        match c {
+            0x00..=0x1f => control = true,
+            0x7f => delete = true,
+            0x80..=0xff => high = true,
+            _ => {}
         }
     }
+
+    let notes: Vec<&str> = [
+        (control, "<0x00> to <0x1F> are control bytes"),
+        (delete, "<DEL> is the delete byte 0x7f"),
+        (high, "<0x80> to <0xFF> are bytes above ASCII"),
+    ].iter().filter(|&&(seen, _)| seen).map(|&(_, note)| note).collect();
+    if notes.is_empty() {
+        None
+    } else {
+        Some(format!("note: {}", notes.join("; ")))
+    }
+}
+
+fn write_table<S, F: Fn(&S) -> String, W: Write>(codes: &[(S, u64, usize)], label: F, output: &mut W) -> Result<(), io::Error> {
+    writeln!(output, "Encoding")?;
+    writeln!(output, "========")?;
+    for (c, code, depth) in codes {
+        writeln!(
+            output,
+            "{0:6} => {1:>#02$b}",
+            label(c),
+            code, depth + 2
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_efficiency<S: Eq + Hash, W: Write>(map: &HashMap<S, u64>, table: &HashMap<S, (u64, usize)>, output: &mut W) -> Result<(), io::Error> {
+    let entropy = rust_huffman::entropy(map);
+    let average = rust_huffman::average_code_length(map, table);
+
+    writeln!(output)?;
+    writeln!(output, "entropy:             {:.4} bits/symbol", entropy)?;
+    writeln!(output, "average code length: {:.4} bits/symbol", average)?;
+    writeln!(output, "efficiency:          {:.2}%", 100.0 * entropy / average)
+}
+
+/// Name the symbols with the longest code, which are always among the rarest.
+fn write_longest<S: Clone, F: Fn(&S) -> String, W: Write>(tree: &rust_huffman::Tree<S>, label: F, output: &mut W) -> Result<(), io::Error> {
+    let symbols: Vec<String> = tree.deepest_symbols().into_iter().map(&label).collect();
+    writeln!(output, "longest code:        {} bits, for {}", tree.depth().max(1), symbols.join(", "))
+}
+
+fn write_costs<S, F, W>(map: &HashMap<S, u64>, table: &HashMap<S, (u64, usize)>, label: F, output: &mut W) -> Result<(), io::Error>
+    where S: Clone + Ord + Hash, F: Fn(&S) -> String, W: Write
+{
+    let costs = rust_huffman::symbol_costs(map, table);
+    let total_bits: u128 = costs.iter().map(|cost| cost.bits).sum();
+
+    writeln!(output)?;
+    writeln!(output, "Cost")?;
+    writeln!(output, "====")?;
+    writeln!(output, "{:8} {:>11} {:>6} {:>14}", "symbol", "probability", "length", "bytes")?;
+    for cost in costs {
+        writeln!(
+            output,
+            "{:8} {:>11.6} {:>6} {:>14.3}",
+            label(&cost.symbol), cost.probability, cost.depth, cost.bits as f64 / 8.0
+        )?;
+    }
+    writeln!(output)?;
+    writeln!(output, "predicted compressed size: {} bytes", total_bits.div_ceil(8))
 }
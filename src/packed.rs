@@ -0,0 +1,167 @@
+//! Coding bytes into and out of buffers the caller provides, using only `core` and `alloc`, so
+//! that this works without the `std` feature. Bits are packed as a `BitWriter` in `LsbFirst`
+//! order packs them.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use tree::Tree;
+use tree::Tree::{Leaf, Node};
+
+/// Everything that can go wrong while packing or unpacking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackError {
+    /// A byte to be packed has no code in the tree.
+    MissingCode(u8),
+    /// The output buffer is too small to hold every code.
+    BufferTooSmall,
+    /// The packed bits ended partway through a code.
+    UnexpectedEof,
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackError::MissingCode(c) => write!(f, "No code for byte {:#04x}", c),
+            PackError::BufferTooSmall => write!(f, "Output buffer is too small"),
+            PackError::UnexpectedEof => write!(f, "Bit stream ended early"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for PackError {}
+
+/// Pack each byte of `input` as its code from `tree` into `output`, returning the number of bits
+/// written. The unused bits of the last byte written are set to zero; bytes after it are left
+/// alone.
+pub fn pack(input: &[u8], tree: &Tree, output: &mut [u8]) -> Result<usize, PackError> {
+    let codes = tree.encode_array();
+    let mut pos = 0usize;
+    for &c in input {
+        let (code, depth) = codes[c as usize];
+        if depth == 0 {
+            return Err(PackError::MissingCode(c));
+        }
+        if pos + depth > output.len() * 8 {
+            return Err(PackError::BufferTooSmall);
+        }
+
+        // Root branch first, from the code's most significant bit.
+        for shift in (0..depth).rev() {
+            let mask = 1u8 << (pos % 8);
+            if (code >> shift) & 1 == 1 {
+                output[pos / 8] |= mask;
+            } else {
+                output[pos / 8] &= !mask;
+            }
+            pos += 1;
+        }
+    }
+
+    if !pos.is_multiple_of(8) {
+        output[pos / 8] &= (1u8 << (pos % 8)) - 1;
+    }
+    Ok(pos)
+}
+
+/// Unpack `count` bytes packed with `tree` from `input` onto the end of `output`, returning the
+/// number of bits read.
+pub fn unpack(input: &[u8], tree: &Tree, count: u64, output: &mut Vec<u8>) -> Result<usize, PackError> {
+    let bits = input.len() * 8;
+    let mut pos = 0usize;
+    let mut next_bit = || {
+        if pos == bits {
+            return Err(PackError::UnexpectedEof);
+        }
+        let bit = (input[pos / 8] >> (pos % 8)) & 1 == 1;
+        pos += 1;
+        Ok(bit)
+    };
+
+    // Every code takes at least one bit, so this bounds the space needed by the input's length.
+    output.reserve(count.min(bits as u64) as usize);
+    for _ in 0..count {
+        let first = next_bit()?;
+        let mut node: &Tree = match tree {
+            Leaf(c, _) => {
+                output.push(*c);
+                continue;
+            }
+            Node(l, r, _) => if first { r } else { l },
+        };
+
+        loop {
+            match node {
+                Leaf(c, _) => break output.push(*c),
+                Node(l, r, _) => node = if next_bit()? { r } else { l },
+            }
+        }
+    }
+
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::Xorshift;
+    use tree::TieBreak;
+
+    /// A tree built as it can be without `std`, from the weights of the bytes in `input`.
+    fn tree_of(input: &[u8]) -> Tree {
+        let mut counts = [0u64; 256];
+        for &c in input {
+            counts[c as usize] += 1;
+        }
+        let weights = (0..=255u8).zip(counts.iter().cloned()).filter(|&(_, count)| count > 0);
+        Tree::from_weights(weights, TieBreak::BySymbol)
+    }
+
+    #[test]
+    fn packed_bytes_unpack_back() {
+        let mut random = Xorshift::new(69);
+        for input in &[random.skewed_bytes(1000), random.bytes(1000, 256), Vec::from(&b"aaaa"[..])] {
+            let tree = tree_of(input);
+            let mut packed = [0xffu8; 2000];
+            let bits = pack(input, &tree, &mut packed).unwrap();
+            assert_eq!(bits as u128, tree.weighted_path_length());
+            // The rest of the last byte is zeroed, and the bytes after it untouched.
+            if !bits.is_multiple_of(8) {
+                assert_eq!(packed[bits / 8] >> (bits % 8), 0);
+            }
+            assert_eq!(packed[bits.div_ceil(8)], 0xff);
+
+            let mut output = Vec::new();
+            assert_eq!(unpack(&packed[..bits.div_ceil(8)], &tree, input.len() as u64, &mut output).unwrap(), bits);
+            assert_eq!(output, *input);
+        }
+    }
+
+    #[test]
+    fn packing_errors() {
+        let tree = tree_of(b"abracadabra");
+        let mut packed = [0u8; 3];
+        assert_eq!(pack(b"abc", &tree, &mut packed[..0]), Err(PackError::BufferTooSmall));
+        assert_eq!(pack(b"abz", &tree, &mut packed), Err(PackError::MissingCode(b'z')));
+
+        let bits = pack(b"abracad", &tree, &mut packed).unwrap();
+        assert!(bits > 8);
+        let mut output = Vec::new();
+        assert_eq!(unpack(&packed[..1], &tree, 7, &mut output), Err(PackError::UnexpectedEof));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn packing_matches_a_bit_writer() {
+        let input = Xorshift::new(96).skewed_bytes(500);
+        let tree = tree_of(&input);
+        let mut writer = ::BitWriter::new(Vec::new());
+        ::encode(&input[..], &tree.encode(), &mut writer).unwrap();
+        let written = writer.finish().unwrap();
+
+        let mut packed = vec![0u8; written.len()];
+        pack(&input, &tree, &mut packed).unwrap();
+        assert_eq!(packed, written);
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::io::{Read, Write, self};
+
+use bits::{BitReader, BitWriter};
+use error::HuffmanError;
+use tree::Tree;
+
+/// The longest code a remapped stream can hold, as its lengths are written in 4 bits.
+pub const REMAP_MAX_LEN: usize = 15;
+
+/// Dense indices `0..len()` for the bytes that occur in a frequency map, in order of byte, so
+/// that a stream can be coded over the indices and keep its code as a bitmap of the bytes and a
+/// code length for each index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolMap {
+    symbols: Vec<u8>,
+    indices: [Option<u8>; 256],
+}
+
+impl SymbolMap {
+    /// Map each byte counted in `freqs`, skipping any counted zero times.
+    pub fn new(freqs: &HashMap<u8, u64>) -> SymbolMap {
+        let mut symbols: Vec<u8> = freqs.iter()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(&c, _)| c)
+            .collect();
+        symbols.sort();
+        SymbolMap::from_symbols(symbols)
+    }
+
+    fn from_symbols(symbols: Vec<u8>) -> SymbolMap {
+        let mut indices = [None; 256];
+        for (i, &c) in symbols.iter().enumerate() {
+            indices[c as usize] = Some(i as u8);
+        }
+        SymbolMap { symbols, indices }
+    }
+
+    /// How many bytes are mapped.
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// The index of byte `c`, if it is mapped.
+    pub fn index(&self, c: u8) -> Option<u8> {
+        self.indices[c as usize]
+    }
+
+    /// The byte at `index`, if there is one.
+    pub fn symbol(&self, index: u8) -> Option<u8> {
+        self.symbols.get(index as usize).cloned()
+    }
+
+    /// `map` keyed by index rather than by byte, leaving out bytes that aren't mapped.
+    pub fn dense_keys<V: Clone>(&self, map: &HashMap<u8, V>) -> HashMap<u8, V> {
+        map.iter()
+            .filter_map(|(&c, v)| self.index(c).map(|i| (i, v.clone())))
+            .collect()
+    }
+
+    /// Replace each byte of `input` with its index.
+    pub fn to_dense(&self, input: &[u8]) -> Result<Vec<u8>, HuffmanError> {
+        input.iter()
+            .map(|&c| self.index(c).ok_or(HuffmanError::MissingCode(c)))
+            .collect()
+    }
+
+    /// Replace each index in `input` with its byte, or `None` if any index is out of range.
+    pub fn from_dense(&self, input: &[u8]) -> Option<Vec<u8>> {
+        input.iter().map(|&i| self.symbol(i)).collect()
+    }
+
+    /// The codes for each byte from `codes`, which is indexed by index, as `encode_array` is.
+    pub(crate) fn byte_codes(&self, codes: &[(u64, usize); 256]) -> Box<[(u64, usize); 256]> {
+        let mut array = Box::new([(0, 0); 256]);
+        for (&c, code) in self.symbols.iter().zip(codes.iter()) {
+            array[c as usize] = *code;
+        }
+        array
+    }
+
+    /// The number of bits `write_code` takes: the bitmap, and 4 bits for each mapped byte.
+    pub fn code_bits(&self) -> u64 {
+        256 + 4 * self.len() as u64
+    }
+
+    /// Write which bytes are mapped as a 256-bit bitmap, lowest byte first, and then the length
+    /// of each index's code in `tree`, which must be coded over the indices, in 4 bits each.
+    ///
+    /// # Panics
+    ///
+    /// If any code is longer than `REMAP_MAX_LEN`, or `tree` doesn't hold every index.
+    pub(crate) fn write_code<W: Write>(&self, tree: &Tree, writer: &mut BitWriter<W>) -> Result<(), io::Error> {
+        for c in 0..=255 {
+            writer.write_bits(self.index(c).is_some() as u64, 1)?;
+        }
+        let lengths = tree.code_lengths();
+        for i in 0..self.len() as u8 {
+            let len = lengths[&i];
+            assert!(len <= REMAP_MAX_LEN, "Code of {} bits is too long to remap", len);
+            writer.write_bits(len as u64, 4)?;
+        }
+        Ok(())
+    }
+
+    /// Read what `write_code` wrote, returning the map and the canonical tree over its indices.
+    pub(crate) fn read_code<R: Read>(reader: &mut BitReader<R>) -> Result<(SymbolMap, Tree), HuffmanError> {
+        let mut symbols = Vec::new();
+        for c in 0..=255 {
+            if reader.read_bits(1)? == 1 {
+                symbols.push(c);
+            }
+        }
+        let map = SymbolMap::from_symbols(symbols);
+        if map.is_empty() {
+            return Err(HuffmanError::IncompletePrefixCode);
+        }
+        let mut lengths = HashMap::new();
+        for i in 0..map.len() as u8 {
+            lengths.insert(i, reader.read_bits(4)? as usize);
+        }
+        let tree = Tree::from_code_lengths(&lengths)?;
+        Ok((map, tree))
+    }
+}
+
+/// Writes the byte for each index written to it.
+pub(crate) struct Unmapper<'a, W: Write> {
+    map: &'a SymbolMap,
+    inner: W,
+}
+
+impl<'a, W: Write> Unmapper<'a, W> {
+    pub(crate) fn new(map: &'a SymbolMap, inner: W) -> Unmapper<'a, W> {
+        Unmapper { map, inner }
+    }
+}
+
+impl<'a, W: Write> Write for Unmapper<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let bytes = self.map.from_dense(buf)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Index isn't mapped to a byte"))?;
+        self.inner.write_all(&bytes)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::{Write, self};
+
+/// Replace each run of a repeated byte in `input` with the byte and the length of the run, as a
+/// pair of bytes. Runs longer than 255 are split. A long run then costs two symbols, and the
+/// lengths of short runs are so common that they take only a bit or two each.
+pub fn run_length_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut rest = input;
+    while let Some(&c) = rest.first() {
+        let run = rest.iter().take(255).take_while(|&&b| b == c).count();
+        output.push(c);
+        output.push(run as u8);
+        rest = &rest[run..];
+    }
+
+    output
+}
+
+/// Reverse `run_length_encode`.
+pub fn run_length_decode(input: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let mut output = RunExpander::new(Vec::new());
+    output.write_all(input)?;
+    output.finish()
+}
+
+/// Expands the pairs of `run_length_encode` as they are written.
+pub(crate) struct RunExpander<W: Write> {
+    inner: W,
+    /// The byte of a pair whose run length hasn't been written yet.
+    pending: Option<u8>,
+    written: u64,
+}
+
+impl<W: Write> RunExpander<W> {
+    pub(crate) fn new(inner: W) -> RunExpander<W> {
+        RunExpander { inner, pending: None, written: 0 }
+    }
+
+    /// The number of bytes the runs have expanded to so far.
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Check that the last pair was complete, and return the inner writer.
+    pub(crate) fn finish(self) -> Result<W, io::Error> {
+        match self.pending {
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Run-length data ends partway through a run")),
+            None => Ok(self.inner),
+        }
+    }
+}
+
+impl<W: Write> Write for RunExpander<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        for &b in buf {
+            match self.pending.take() {
+                Some(c) => {
+                    if b == 0 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Run-length data has a run of length 0"));
+                    }
+                    self.inner.write_all(&[c; 255][..b as usize])?;
+                    self.written += u64::from(b);
+                }
+                None => self.pending = Some(b),
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_become_pairs_and_back() {
+        let mut input = vec![b'a'; 600];
+        input.extend(b"bcc");
+        let encoded = run_length_encode(&input);
+        assert_eq!(encoded, [b'a', 255, b'a', 255, b'a', 90, b'b', 1, b'c', 2]);
+        assert_eq!(run_length_decode(&encoded).unwrap(), input);
+        assert!(run_length_encode(b"").is_empty());
+    }
+
+    #[test]
+    fn malformed_pairs_are_rejected() {
+        assert_eq!(run_length_decode(&[b'a', 3, b'b']).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(run_length_decode(&[b'a', 0]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Shannon entropy of a frequency map, in bits per symbol.
+pub fn entropy<S>(freqs: &HashMap<S, u64>) -> f64 {
+    let total = total(freqs) as f64;
+    freqs.values()
+        .filter(|&&count| count > 0)
+        // Written so that a single symbol, or none, comes out as 0 rather than -0.
+        .fold(0.0, |sum, &count| {
+            let p = count as f64 / total;
+            sum + p * (total / count as f64).log2()
+        })
+}
+
+/// Average number of bits `table` spends on each symbol counted in `freqs`.
+pub fn average_code_length<S: Eq + Hash>(freqs: &HashMap<S, u64>, table: &HashMap<S, (u64, usize)>) -> f64 {
+    let total = total(freqs) as f64;
+    let bits: u128 = freqs.iter()
+        .map(|(c, &count)| u128::from(count) * table[c].1 as u128)
+        .sum();
+    bits as f64 / total
+}
+
+/// What one symbol costs under a code table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolCost<S> {
+    pub symbol: S,
+    pub count: u64,
+    /// The symbol's share of all symbols counted.
+    pub probability: f64,
+    pub depth: usize,
+    /// Total bits spent on the symbol, `count * depth`, which may be more than a `u64` holds.
+    pub bits: u128,
+}
+
+/// The cost of each symbol counted in `freqs`, most expensive first.
+pub fn symbol_costs<S: Clone + Ord + Hash>(freqs: &HashMap<S, u64>, table: &HashMap<S, (u64, usize)>) -> Vec<SymbolCost<S>> {
+    let total = total(freqs) as f64;
+    let mut costs: Vec<_> = freqs.iter()
+        .map(|(c, &count)| {
+            let depth = table[c].1;
+            let bits = u128::from(count) * depth as u128;
+            SymbolCost { symbol: c.clone(), count, probability: count as f64 / total, depth, bits }
+        })
+        .collect();
+
+    costs.sort_by(|l, r| r.bits.cmp(&l.bits).then_with(|| l.symbol.cmp(&r.symbol)));
+    costs
+}
+
+/// A summary of a frequency map, which needs no codes to work out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// How many symbols were counted in all, which may be more than a `u64` holds.
+    pub total: u128,
+    pub distinct: usize,
+    pub min: u64,
+    pub max: u64,
+    pub mean: f64,
+    /// Shannon entropy, in bits per symbol.
+    pub entropy: f64,
+}
+
+/// The entropy, in bits per byte, from which `Analysis::incompressible` is usually asked about:
+/// within a twentieth of a bit of the eight that each byte takes already.
+pub const INCOMPRESSIBLE_ENTROPY: f64 = 7.95;
+
+impl Analysis {
+    /// The fewest bytes any code could take for the counted symbols, `entropy * total / 8`.
+    pub fn optimal_size(&self) -> f64 {
+        self.entropy * self.total as f64 / 8.0
+    }
+
+    /// Whether the entropy is at least `threshold` bits per symbol, so that for bytes, as with
+    /// data that is already compressed, there is next to nothing for coding to save and the
+    /// header and tree will likely make the output larger than the input.
+    pub fn incompressible(&self, threshold: f64) -> bool {
+        self.total > 0 && self.entropy >= threshold
+    }
+}
+
+/// Summarise `freqs`, skipping symbols counted zero times.
+pub fn analyze<S>(freqs: &HashMap<S, u64>) -> Analysis {
+    let counts: Vec<u64> = freqs.values().cloned().filter(|&count| count > 0).collect();
+    let total = total(freqs);
+    Analysis {
+        total,
+        distinct: counts.len(),
+        min: counts.iter().cloned().min().unwrap_or(0),
+        max: counts.iter().cloned().max().unwrap_or(0),
+        mean: if counts.is_empty() { 0.0 } else { total as f64 / counts.len() as f64 },
+        entropy: if counts.is_empty() { 0.0 } else { entropy(freqs) },
+    }
+}
+
+/// The sum of the counts in `freqs`, which can't overflow however large each one is.
+fn total<S>(freqs: &HashMap<S, u64>) -> u128 {
+    freqs.values().map(|&count| u128::from(count)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tree::Tree;
+
+    #[test]
+    fn four_equally_likely_symbols_take_two_bits() {
+        let freqs: HashMap<u8, u64> = b"abcd".iter().map(|&c| (c, 10)).collect();
+        let table = Tree::from(freqs.clone()).encode();
+        assert_eq!(entropy(&freqs), 2.0);
+        assert_eq!(average_code_length(&freqs, &table), 2.0);
+    }
+
+    /// The textbook example, whose codes are 1 bit for 'A', 3 for 'B', 'C' and 'D' and 4 for
+    /// 'E' and 'F'.
+    fn classic() -> HashMap<u8, u64> {
+        [(b'A', 45), (b'B', 13), (b'C', 12), (b'D', 16), (b'E', 9), (b'F', 5)].iter().cloned().collect()
+    }
+
+    #[test]
+    fn symbol_costs_are_sorted_by_their_share_of_the_output() {
+        let freqs = classic();
+        let costs = symbol_costs(&freqs, &Tree::from(freqs.clone()).encode());
+        let summary: Vec<_> = costs.iter().map(|cost| (cost.symbol, cost.depth, cost.bits)).collect();
+        assert_eq!(summary, [(b'D', 3, 48), (b'A', 1, 45), (b'B', 3, 39), (b'C', 3, 36), (b'E', 4, 36), (b'F', 4, 20)]);
+        assert_eq!(costs.iter().map(|cost| cost.bits).sum::<u128>(), 224);
+        assert_eq!(costs.iter().map(|cost| cost.probability).sum::<f64>(), 1.0);
+    }
+
+    #[test]
+    fn analysis_summarises_the_counts() {
+        let mut freqs = classic();
+        freqs.insert(b'G', 0);
+        let analysis = analyze(&freqs);
+        assert_eq!((analysis.total, analysis.distinct, analysis.min, analysis.max), (100, 6, 5, 45));
+        assert!((analysis.mean - 100.0 / 6.0).abs() < 1e-12);
+        assert!((analysis.entropy - 2.2199).abs() < 1e-4);
+        assert!(!analysis.incompressible(INCOMPRESSIBLE_ENTROPY));
+
+        let empty = analyze(&HashMap::<u8, u64>::new());
+        assert_eq!((empty.total, empty.distinct, empty.mean, empty.entropy), (0, 0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn counts_near_u64_max_dont_overflow() {
+        let freqs: HashMap<u8, u64> = [(b'a', u64::MAX), (b'b', u64::MAX), (b'c', u64::MAX)].iter().cloned().collect();
+        let table = Tree::from(freqs.clone()).encode();
+        let max = u128::from(u64::MAX);
+
+        assert_eq!(analyze(&freqs).total, 3 * max);
+        assert!((entropy(&freqs) - 3f64.log2()).abs() < 1e-12);
+        assert!((average_code_length(&freqs, &table) - 5.0 / 3.0).abs() < 1e-12);
+        assert_eq!(symbol_costs(&freqs, &table).iter().map(|cost| cost.bits).sum::<u128>(), 5 * max);
+    }
+
+    #[test]
+    fn every_byte_alike_is_incompressible() {
+        let freqs: HashMap<u8, u64> = (0..=255).map(|c| (c, 10)).collect();
+        let analysis = analyze(&freqs);
+        assert_eq!(analysis.entropy, 8.0);
+        assert!(analysis.incompressible(INCOMPRESSIBLE_ENTROPY));
+        assert!(analysis.incompressible(8.0));
+        assert!(!analysis.incompressible(8.01));
+
+        // Nothing at all has nothing to warn about.
+        assert!(!analyze(&HashMap::<u8, u64>::new()).incompressible(0.0));
+    }
+}
@@ -0,0 +1,32 @@
+//! Helpers shared by the unit tests.
+
+use alloc::vec::Vec;
+
+/// Pseudo-random numbers from xorshift64, so that tests see the same data on every run without
+/// the crate depending on a random number generator.
+pub(crate) struct Xorshift(u64);
+
+impl Xorshift {
+    pub(crate) fn new(seed: u64) -> Xorshift {
+        // Xorshift never leaves zero.
+        Xorshift(seed | 1)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// `len` bytes, each below `limit`, or any byte with a `limit` of 256.
+    pub(crate) fn bytes(&mut self, len: usize, limit: u64) -> Vec<u8> {
+        (0..len).map(|_| (self.next_u64() % limit) as u8).collect()
+    }
+
+    /// `len` bytes, each half as likely as the one below it, so that in a few thousand of them
+    /// the rarest get codes longer than a byte.
+    pub(crate) fn skewed_bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next_u64().trailing_zeros() as u8).collect()
+    }
+}
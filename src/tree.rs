@@ -0,0 +1,1240 @@
+use alloc::boxed::Box;
+use alloc::collections::{BinaryHeap, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use core::convert::TryFrom;
+#[cfg(feature = "std")]
+use core::fmt::Display;
+use core::ops::Add;
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use std::hash::Hash;
+#[cfg(feature = "std")]
+use std::io::{Read, Write, self};
+
+#[cfg(feature = "std")]
+use bits::{BitReader, BitWriter};
+#[cfg(feature = "std")]
+use error::HuffmanError;
+
+/// A Huffman tree over symbols (bytes unless stated otherwise), each leaf weighted by how often
+/// its symbol occurs, or by any other `Weight`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Tree<S = u8, P = u64> {
+    Leaf(S, P),
+    Node(Box<Tree<S, P>>, Box<Tree<S, P>>, P),
+}
+use self::Tree::*;
+
+/// A weight that trees can be built from, such as a probability or a cost. Weights must be
+/// totally ordered, so floating-point weights need a wrapper that orders them.
+pub trait Weight: Add<Output = Self> + Ord + Copy {
+    /// The weight of a node joining subtrees of weights `self` and `other`: their sum, unless
+    /// that would overflow.
+    fn join(self, other: Self) -> Self {
+        self + other
+    }
+}
+
+/// Integer weights saturate rather than wrapping, which would let the heaviest subtrees jump to
+/// the front of the construction queue.
+macro_rules! saturating_weight {
+    ($($t:ty),*) => {
+        $(impl Weight for $t {
+            fn join(self, other: $t) -> $t {
+                self.saturating_add(other)
+            }
+        })*
+    };
+}
+
+saturating_weight!(u8, u16, u32, u64, u128, usize);
+
+impl<S, P: Copy> Tree<S, P> {
+    pub fn prob(&self) -> P {
+        match self {
+            Leaf(_, p) => *p,
+            Node(_, _, p) => *p,
+        }
+    }
+}
+
+impl<S, P> Tree<S, P> {
+    /// The number of branches from the root down to the deepest leaf. A lone leaf has depth 0,
+    /// even though `encode` still gives it a 1-bit code.
+    pub fn depth(&self) -> usize {
+        match self {
+            Leaf(..) => 0,
+            Node(l, r, _) => 1 + l.depth().max(r.depth()),
+        }
+    }
+
+    /// The number of leaves, which is the number of symbols the tree codes.
+    pub fn leaf_count(&self) -> usize {
+        match self {
+            Leaf(..) => 1,
+            Node(l, r, _) => l.leaf_count() + r.leaf_count(),
+        }
+    }
+
+    /// How many codes there are of each length, indexed by length, up to the longest. This is all
+    /// a canonical code sends to describe its shape. As in `encode`, a lone leaf has a 1-bit code.
+    pub fn length_histogram(&self) -> Vec<u64> {
+        fn recurse<S, P>(node: &Tree<S, P>, depth: usize, counts: &mut [u64]) {
+            match node {
+                Leaf(..) => counts[depth] += 1,
+                Node(l, r, _) => {
+                    recurse(l, depth + 1, counts);
+                    recurse(r, depth + 1, counts);
+                }
+            }
+        }
+
+        let mut counts = vec![0; self.depth().max(1) + 1];
+        match self {
+            Leaf(..) => counts[1] = 1,
+            Node(..) => recurse(self, 0, &mut counts),
+        }
+        counts
+    }
+
+    /// The symbols with the longest codes, each `depth` branches below the root, from left to
+    /// right. Very deep codes mean very skewed weights, and may be worth limiting with
+    /// `build_length_limited`.
+    pub fn deepest_symbols(&self) -> Vec<&S> {
+        fn recurse<'a, S, P>(node: &'a Tree<S, P>, depth: usize, out: &mut Vec<&'a S>) {
+            match node {
+                Leaf(c, _) => if depth == 0 { out.push(c) },
+                Node(l, r, _) if depth > 0 => {
+                    recurse(l, depth - 1, out);
+                    recurse(r, depth - 1, out);
+                }
+                Node(..) => {}
+            }
+        }
+
+        let mut out = Vec::new();
+        recurse(self, self.depth(), &mut out);
+        out
+    }
+}
+
+impl<S: Copy, P> Tree<S, P> {
+    /// The symbols of the leaves from left to right, which is in order of code, found lazily as
+    /// the iterator is advanced.
+    pub fn symbols(&self) -> impl Iterator<Item = S> + '_ {
+        Symbols { stack: vec![self] }
+    }
+}
+
+/// The walk behind `Tree::symbols`: the subtrees still to visit, the next on top.
+struct Symbols<'a, S: 'a, P: 'a> {
+    stack: Vec<&'a Tree<S, P>>,
+}
+
+impl<'a, S: Copy, P> Iterator for Symbols<'a, S, P> {
+    type Item = S;
+
+    fn next(&mut self) -> Option<S> {
+        loop {
+            match self.stack.pop()? {
+                Leaf(c, _) => return Some(*c),
+                Node(l, r, _) => {
+                    self.stack.push(r);
+                    self.stack.push(l);
+                }
+            }
+        }
+    }
+}
+
+impl<S> Tree<S> {
+    /// The sum over every leaf of its weight times the length of its code, which is how many bits
+    /// coding the counted symbols takes. A lone leaf counts its 1-bit code. Summed in `u128`, as
+    /// counts near `u64::MAX` several bits deep take more bits than a `u64` holds.
+    pub fn weighted_path_length(&self) -> u128 {
+        fn recurse<S>(node: &Tree<S>, depth: u128) -> u128 {
+            match node {
+                Leaf(_, p) => u128::from(*p) * depth,
+                Node(l, r, _) => recurse(l, depth + 1) + recurse(r, depth + 1),
+            }
+        }
+
+        match self {
+            Leaf(_, p) => u128::from(*p),
+            Node(..) => recurse(self, 0),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S, P: Display> Tree<S, P> {
+    /// Render the tree as a diagram, with each branch labelled by its bit and each leaf by
+    /// `label` and its weight.
+    pub fn draw_with<F: Fn(&S) -> String>(&self, label: F) -> String {
+        fn recurse<S, P: Display, F: Fn(&S) -> String>(node: &Tree<S, P>, label: &F, prefix: &str, out: &mut String) {
+            match node {
+                Leaf(c, p) => out.push_str(&format!("{} ({})\n", label(c), p)),
+                Node(l, r, p) => {
+                    out.push_str(&format!("({})\n", p));
+                    out.push_str(&format!("{}├─0─ ", prefix));
+                    recurse(l, label, &format!("{}│    ", prefix), out);
+                    out.push_str(&format!("{}└─1─ ", prefix));
+                    recurse(r, label, &format!("{}     ", prefix), out);
+                }
+            }
+        }
+
+        let mut out = String::new();
+        recurse(self, &label, "", &mut out);
+        out
+    }
+
+    /// Render the tree as a Graphviz `digraph`, with nodes showing their weights, leaves also
+    /// showing `label`, and edges labelled by their bit.
+    pub fn to_dot_with<F: Fn(&S) -> String>(&self, label: F) -> String {
+        fn recurse<S, P: Display, F: Fn(&S) -> String>(node: &Tree<S, P>, label: &F, id: &mut usize, out: &mut String) -> usize {
+            let this = *id;
+            *id += 1;
+            match node {
+                Leaf(c, p) => {
+                    let text = format!("{} ({})", label(c), p).replace('\\', "\\\\").replace('"', "\\\"");
+                    out.push_str(&format!("    n{} [label=\"{}\", shape=box];\n", this, text));
+                }
+                Node(l, r, p) => {
+                    out.push_str(&format!("    n{} [label=\"{}\"];\n", this, p));
+                    for (bit, child) in [(0, l), (1, r)] {
+                        let child = recurse(child, label, id, out);
+                        out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", this, child, bit));
+                    }
+                }
+            }
+            this
+        }
+
+        let mut out = String::from("digraph huffman {\n");
+        recurse(self, &label, &mut 0, &mut out);
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Clone + Eq + Hash, P> Tree<S, P> {
+    /// Check that the tree is a usable prefix code. Every node already has two children, so each
+    /// path from the root ends at a leaf and none passes through one; what is left to check is
+    /// that no symbol has two leaves, which would leave it with two codes, and that no code is
+    /// longer than the 64 bits a code is held in.
+    pub fn validate(&self) -> Result<(), HuffmanError> {
+        fn recurse<'a, S: Eq + Hash, P>(node: &'a Tree<S, P>, seen: &mut HashSet<&'a S>, depth: usize) -> Result<(), HuffmanError> {
+            if depth > 64 {
+                return Err(HuffmanError::InvalidCodeLength(depth));
+            }
+            match node {
+                Leaf(c, _) => if seen.insert(c) { Ok(()) } else { Err(HuffmanError::IncompletePrefixCode) },
+                Node(l, r, _) => {
+                    recurse(l, seen, depth + 1)?;
+                    recurse(r, seen, depth + 1)
+                }
+            }
+        }
+
+        recurse(self, &mut HashSet::new(), 0)
+    }
+
+    pub fn encode(&self) -> HashMap<S, (u64, usize)> {
+        fn recurse<S: Clone + Eq + Hash, P>(node: &Tree<S, P>, map: &mut HashMap<S, (u64, usize)>, prefix: u64, depth: usize) {
+            match node {
+                Leaf(c, _) => {
+                    map.insert(c.clone(), (prefix, depth));
+                }
+                Node(l, r, _) => {
+                    recurse(l, map, prefix << 1, depth + 1);
+                    recurse(r, map, (prefix << 1) | 1, depth + 1);
+                }
+            }
+        }
+
+        let mut map = HashMap::new();
+        match self {
+            // A lone symbol still needs one bit per occurrence so that it can be counted back out.
+            Leaf(c, _) => {
+                map.insert(c.clone(), (0, 1));
+            }
+            Node(..) => recurse(self, &mut map, 0, 0),
+        }
+        map
+    }
+
+    /// The length of each symbol's code from `encode`, without working out the codes themselves.
+    /// This is all that canonical codes, as in DEFLATE, need to be rebuilt from.
+    pub fn code_lengths(&self) -> HashMap<S, usize> {
+        fn recurse<S: Clone + Eq + Hash, P>(node: &Tree<S, P>, map: &mut HashMap<S, usize>, depth: usize) {
+            match node {
+                Leaf(c, _) => {
+                    map.insert(c.clone(), depth);
+                }
+                Node(l, r, _) => {
+                    recurse(l, map, depth + 1);
+                    recurse(r, map, depth + 1);
+                }
+            }
+        }
+
+        let mut map = HashMap::new();
+        match self {
+            Leaf(c, _) => {
+                map.insert(c.clone(), 1);
+            }
+            Node(..) => recurse(self, &mut map, 0),
+        }
+        map
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Clone + Ord + Hash, P> Tree<S, P> {
+    /// Each symbol with its `(code, depth)` from `encode`, shortest codes first and then in
+    /// order of code.
+    pub fn iter_codes(&self) -> impl Iterator<Item = (S, u64, usize)> {
+        let mut codes: Vec<_> = self.encode().into_iter()
+            .map(|(c, (code, depth))| (c, code, depth))
+            .collect();
+        codes.sort_by(|(_, l_code, l_depth), (_, r_code, r_depth)| (l_depth, l_code).cmp(&(r_depth, r_code)));
+        codes.into_iter()
+    }
+
+    /// Codes with the same lengths as `encode`, but assigned canonically so that they depend only
+    /// on those lengths. Within each length, codes increase with the symbol value.
+    pub fn canonical_encode(&self) -> HashMap<S, (u64, usize)> {
+        canonical_codes(self.code_lengths())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Clone + Ord + Hash> Tree<S> {
+    /// Rebuild the tree that assigns the canonical codes for `lengths`, as a decoder that was only
+    /// sent code lengths must. The lengths have to form a complete prefix code, with the
+    /// exception of a lone symbol of length 1. Weights aren't known, so they are all zero.
+    pub fn from_code_lengths(lengths: &HashMap<S, usize>) -> Result<Tree<S>, HuffmanError> {
+        if let Some(&len) = lengths.values().find(|&&len| len == 0 || len > 64) {
+            return Err(HuffmanError::InvalidCodeLength(len));
+        }
+        match lengths.len() {
+            0 => return Err(HuffmanError::EmptyInput),
+            1 => {
+                let (c, &len) = lengths.iter().next().unwrap();
+                return if len == 1 { Ok(Leaf(c.clone(), 0)) } else { Err(HuffmanError::IncompletePrefixCode) };
+            }
+            _ => {}
+        }
+
+        // Kraft's sum of 2^-len, scaled by 2^64, is exactly 1 for a complete prefix code.
+        let kraft: u128 = lengths.values().map(|&len| 1u128 << (64 - len)).sum();
+        if kraft != 1u128 << 64 {
+            return Err(HuffmanError::IncompletePrefixCode);
+        }
+
+        fn build<S: Clone>(codes: &[(u64, usize, S)], depth: usize) -> Tree<S> {
+            match codes {
+                [(_, len, c)] if *len == depth => Leaf(c.clone(), 0),
+                _ => {
+                    let split = codes.iter()
+                        .position(|&(code, len, _)| (code >> (len - depth - 1)) & 1 == 1)
+                        .unwrap_or(codes.len());
+                    build(&codes[..split], depth + 1) + build(&codes[split..], depth + 1)
+                }
+            }
+        }
+
+        let mut codes: Vec<_> = canonical_codes(lengths.iter().map(|(c, &len)| (c.clone(), len)))
+            .into_iter()
+            .map(|(c, (code, len))| (code, len, c))
+            .collect();
+        codes.sort_by_key(|&(code, len, _)| code << (64 - len));
+        Ok(build(&codes, 0))
+    }
+}
+
+impl<P> Tree<u8, P> {
+    /// The same codes as `encode`, indexed by byte. Bytes the tree doesn't hold have depth 0.
+    pub fn encode_array(&self) -> Box<[(u64, usize); 256]> {
+        fn recurse<P>(node: &Tree<u8, P>, array: &mut [(u64, usize); 256], prefix: u64, depth: usize) {
+            match node {
+                Leaf(c, _) => array[*c as usize] = (prefix, depth),
+                Node(l, r, _) => {
+                    recurse(l, array, prefix << 1, depth + 1);
+                    recurse(r, array, (prefix << 1) | 1, depth + 1);
+                }
+            }
+        }
+
+        let mut array = Box::new([(0u64, 0usize); 256]);
+        match self {
+            // As in `encode`, a lone symbol gets a 1-bit code.
+            Leaf(c, _) => array[*c as usize] = (0, 1),
+            Node(..) => recurse(self, &mut array, 0, 0),
+        }
+        array
+    }
+
+    /// The number of bits `serialize` writes.
+    pub fn serialized_len(&self) -> u64 {
+        match self {
+            Leaf(..) => 9,
+            Node(l, r, _) => 1 + l.serialized_len() + r.serialized_len(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: Display> Tree<u8, P> {
+    /// Render the tree as a diagram; see `draw_with`.
+    pub fn draw(&self) -> String {
+        self.draw_with(|&c| byte_label(c))
+    }
+
+    /// Render the tree as a Graphviz `digraph`; see `to_dot_with`.
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(|&c| byte_label(c))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Tree<u8> {
+    /// Write the shape of the tree in pre-order: a 1 bit for each node followed by its children,
+    /// and a 0 bit followed by the 8-bit symbol for each leaf.
+    pub fn serialize<W: Write>(&self, w: &mut BitWriter<W>) -> Result<(), io::Error> {
+        match self {
+            Leaf(c, _) => {
+                w.write_bits(0, 1)?;
+                w.write_bits(u64::from(*c), 8)
+            }
+            Node(l, r, _) => {
+                w.write_bits(1, 1)?;
+                l.serialize(w)?;
+                r.serialize(w)
+            }
+        }
+    }
+
+    /// Read a tree written by `serialize`. Weights aren't stored, so they all come back as zero.
+    ///
+    /// A tree of bytes has at most 256 leaves, so at most 255 nodes. Reading stops with
+    /// `AlphabetTooLarge` at any more than that, which also bounds how deep a crafted stream can
+    /// make the recursion go.
+    pub fn deserialize<R: Read>(r: &mut BitReader<R>) -> Result<Tree, HuffmanError> {
+        fn recurse<R: Read>(r: &mut BitReader<R>, nodes: &mut usize) -> Result<Tree, HuffmanError> {
+            if r.read_bits(1)? == 1 {
+                *nodes += 1;
+                if *nodes > 255 {
+                    return Err(HuffmanError::AlphabetTooLarge(256));
+                }
+                let left = recurse(r, nodes)?;
+                let right = recurse(r, nodes)?;
+                Ok(left + right)
+            } else {
+                Ok(Leaf(r.read_bits(8)? as u8, 0))
+            }
+        }
+
+        recurse(r, &mut 0)
+    }
+}
+
+/// Parse a tree from the bytes `serialize` writes with a `BitWriter` of the default order, from
+/// the root to the padding that ends the last byte, checking that it is a usable prefix code.
+/// Anything after the padding is an error.
+#[cfg(feature = "std")]
+impl<'a> TryFrom<&'a [u8]> for Tree {
+    type Error = HuffmanError;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Tree, HuffmanError> {
+        let mut reader = BitReader::new(bytes);
+        let tree = Tree::deserialize(&mut reader)?;
+        tree.validate()?;
+
+        reader.skip_to_byte();
+        if reader.read_bit()?.is_some() {
+            return Err(HuffmanError::InvalidHeader("Data follows the serialized tree".to_string()));
+        }
+        Ok(tree)
+    }
+}
+
+/// Join two trees under a new node, weighted by `Weight::join`.
+impl<S, P: Weight> Add for Tree<S, P> {
+    type Output = Self;
+
+    fn add(self: Tree<S, P>, right: Tree<S, P>) -> Tree<S, P> {
+        let total_prob = self.prob().join(right.prob());
+        Node(Box::new(self), Box::new(right), total_prob)
+    }
+}
+
+/// Reversed on weight, so that a max-heap such as `BinaryHeap` pops the lightest tree first.
+/// Trees of equal weight fall back to comparing their structure: leaves before nodes, then
+/// symbols, weights and children in turn, so only equal trees compare equal.
+impl<S: Ord, P: Ord + Copy> Ord for Tree<S, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        fn structure<S: Ord, P: Ord>(l: &Tree<S, P>, r: &Tree<S, P>) -> Ordering {
+            match (l, r) {
+                (Leaf(l_c, l_p), Leaf(r_c, r_p)) => l_c.cmp(r_c).then(l_p.cmp(r_p)),
+                (Leaf(..), Node(..)) => Ordering::Less,
+                (Node(..), Leaf(..)) => Ordering::Greater,
+                (Node(l_l, l_r, l_p), Node(r_l, r_r, r_p)) => structure(l_l, r_l)
+                    .then_with(|| structure(l_r, r_r))
+                    .then(l_p.cmp(r_p)),
+            }
+        }
+
+        other.prob().cmp(&self.prob())
+            .then_with(|| structure(other, self))
+    }
+}
+
+impl<S: Ord, P: Ord + Copy> PartialOrd for Tree<S, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// How `Tree::from_with_tiebreak` chooses between subtrees of equal weight. Every strategy
+/// builds an optimal tree, so the total coded size is the same, but the code each symbol gets can
+/// differ, and so can the individual code lengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// Take the subtree holding the smallest symbol first.
+    BySymbol,
+    /// Take the subtree that joined the queue first: leaves in symbol order, then each joined
+    /// subtree as it is made, counted as it joins. This matches the usual two-queue construction,
+    /// which prefers leaves, and is what `Tree::from` does.
+    #[default]
+    ByInsertion,
+    /// Take the shallowest subtree first, breaking any remaining ties by symbol. This keeps the
+    /// longest code as short as possible.
+    Minimal,
+}
+
+impl<S: Clone + Ord, P: Weight> Tree<S, P> {
+    /// Repeatedly join the two lightest subtrees, lighter on the left, choosing between subtrees
+    /// of equal weight with `tiebreak`. The same frequencies always build the same tree.
+    ///
+    /// Every tie-break builds an optimal tree, so when every weight is equal, the codes are as
+    /// balanced as they can be: `n` symbols get codes of `floor(log2(n))` or `ceil(log2(n))`
+    /// bits, and all 256 bytes counted equally get 8 bits each.
+    #[cfg(feature = "std")]
+    pub fn from_with_tiebreak(probs: HashMap<S, P>, tiebreak: TieBreak) -> Tree<S, P> {
+        Tree::from_weights(probs, tiebreak)
+    }
+
+    /// Like `from_with_tiebreak`, but taking the weights as `(symbol, weight)` pairs, each symbol
+    /// at most once, in any order. This needs only `alloc`, not `std`.
+    ///
+    /// # Panics
+    ///
+    /// If `weights` is empty.
+    pub fn from_weights<I: IntoIterator<Item = (S, P)>>(weights: I, tiebreak: TieBreak) -> Tree<S, P> {
+        // Sort first so that the heap starts out the same whatever order the pairs come in.
+        let mut leaves: Vec<_> = weights.into_iter().collect();
+        leaves.sort();
+        let mut inserted = 0usize;
+        let mut queue = BinaryHeap::new();
+        for (c, count) in leaves {
+            let rank = match tiebreak {
+                TieBreak::BySymbol | TieBreak::Minimal => 0,
+                TieBreak::ByInsertion => inserted,
+            };
+            inserted += 1;
+            queue.push(Pending { rank, min: c.clone(), tree: Leaf(c, count) });
+        }
+
+        while queue.len() > 1 {
+            let first = queue.pop().unwrap();
+            let second = queue.pop().unwrap();
+            let rank = match tiebreak {
+                TieBreak::BySymbol => 0,
+                TieBreak::ByInsertion => inserted,
+                TieBreak::Minimal => 1 + first.rank.max(second.rank),
+            };
+            inserted += 1;
+            queue.push(Pending {
+                rank,
+                min: first.min.min(second.min),
+                tree: first.tree + second.tree,
+            })
+        }
+
+        queue.pop().expect("At least one character").tree
+    }
+
+    /// Build the tree for `(symbol, weight)` pairs that are already sorted by weight and then by
+    /// symbol, in linear time with two queues: one of the leaves, and one of the joined subtrees,
+    /// which are made in order of weight and so never need sorting. Ties go to the leaves, so
+    /// this builds the same tree as `from_with_tiebreak` with `TieBreak::ByInsertion`.
+    ///
+    /// # Panics
+    ///
+    /// If `sorted` is empty or isn't sorted.
+    pub fn from_sorted(sorted: Vec<(S, P)>) -> Tree<S, P> {
+        assert!(
+            sorted.windows(2).all(|pair| (pair[0].1, &pair[0].0) < (pair[1].1, &pair[1].0)),
+            "Weights must be sorted, with equal weights in order of symbol"
+        );
+        let mut leaves: VecDeque<_> = sorted.into_iter().map(|(c, count)| Leaf(c, count)).collect();
+        let mut joined = VecDeque::new();
+
+        let lightest = |leaves: &mut VecDeque<Tree<S, P>>, joined: &mut VecDeque<Tree<S, P>>| {
+            let take_leaf = match (leaves.front(), joined.front()) {
+                (Some(leaf), Some(tree)) => leaf.prob() <= tree.prob(),
+                (leaf, _) => leaf.is_some(),
+            };
+            if take_leaf { leaves.pop_front() } else { joined.pop_front() }
+        };
+        while leaves.len() + joined.len() > 1 {
+            let first = lightest(&mut leaves, &mut joined).unwrap();
+            let second = lightest(&mut leaves, &mut joined).unwrap();
+            joined.push_back(first + second);
+        }
+
+        lightest(&mut leaves, &mut joined).expect("At least one character")
+    }
+}
+
+/// Builds the tree with `TieBreak::ByInsertion`, in a heap as `from_with_tiebreak` does. With the
+/// `two-queue` feature, the weights are sorted for `Tree::from_sorted` instead, which builds the
+/// same tree.
+#[cfg(feature = "std")]
+impl<S: Clone + Ord, P: Weight> From<HashMap<S, P>> for Tree<S, P> {
+    #[cfg(not(feature = "two-queue"))]
+    fn from(probs: HashMap<S, P>) -> Tree<S, P> {
+        Tree::from_with_tiebreak(probs, TieBreak::ByInsertion)
+    }
+
+    #[cfg(feature = "two-queue")]
+    fn from(probs: HashMap<S, P>) -> Tree<S, P> {
+        let mut sorted: Vec<_> = probs.into_iter().collect();
+        sorted.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+        Tree::from_sorted(sorted)
+    }
+}
+
+/// A subtree waiting to be joined, along with what its ties are broken on: a rank set by the
+/// `TieBreak`, then the smallest symbol it holds.
+struct Pending<S, P> {
+    tree: Tree<S, P>,
+    rank: usize,
+    min: S,
+}
+
+/// Reversed, so that the max-heap `BinaryHeap` pops the lightest subtree first.
+impl<S: Ord, P: Ord + Copy> Ord for Pending<S, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.tree.prob().cmp(&self.tree.prob())
+            .then_with(|| other.rank.cmp(&self.rank))
+            .then_with(|| other.min.cmp(&self.min))
+    }
+}
+
+impl<S: Ord, P: Ord + Copy> PartialOrd for Pending<S, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: Ord, P: Ord + Copy> PartialEq for Pending<S, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<S: Ord, P: Ord + Copy> Eq for Pending<S, P> {}
+
+/// Printable ASCII as a quoted character, and anything else as hex.
+#[cfg(feature = "std")]
+pub fn byte_label(c: u8) -> String {
+    if c == b' ' || c.is_ascii_graphic() {
+        format!("{:?}", c as char)
+    } else {
+        format!("{:#04x}", c)
+    }
+}
+
+/// Assign canonical codes to a set of code lengths: symbols are ordered by (length, symbol), each
+/// taking the code after the previous one, shifted left whenever the length grows.
+#[cfg(feature = "std")]
+pub(crate) fn canonical_codes<S, I>(lengths: I) -> HashMap<S, (u64, usize)>
+    where S: Ord + Hash, I: IntoIterator<Item = (S, usize)>
+{
+    let mut lengths: Vec<_> = lengths.into_iter().collect();
+    lengths.sort_by(|(l_c, l_depth), (r_c, r_depth)| (l_depth, l_c).cmp(&(r_depth, r_c)));
+
+    let mut map = HashMap::new();
+    let mut code = 0u64;
+    let mut prev_depth = lengths.first().map_or(0, |&(_, depth)| depth);
+    for (c, depth) in lengths {
+        code <<= depth - prev_depth;
+        map.insert(c, (code, depth));
+        // Only wraps after the last code of a complete code of 64 bits, which is all ones.
+        code = code.wrapping_add(1);
+        prev_depth = depth;
+    }
+
+    map
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use testing::Xorshift;
+    use {count_frequencies, decode, encode};
+
+    fn tree_of(input: &[u8]) -> Tree {
+        Tree::from(count_frequencies(input).unwrap())
+    }
+
+    /// `tree` with every weight zeroed, as a tree read back from its serialized form is.
+    fn without_weights(tree: &Tree) -> Tree {
+        match tree {
+            Leaf(c, _) => Leaf(*c, 0),
+            Node(l, r, _) => without_weights(l) + without_weights(r),
+        }
+    }
+
+    #[test]
+    fn serialized_trees_read_back_the_same() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let inputs: [&[u8]; 4] = [b"a", b"abracadabra", b"the quick brown fox jumps over the lazy dog", &all_bytes];
+        for input in inputs.iter() {
+            let tree = tree_of(input);
+            let mut writer = BitWriter::new(Vec::new());
+            tree.serialize(&mut writer).unwrap();
+            let bytes = writer.finish().unwrap();
+
+            let mut reader = BitReader::new(&bytes[..]);
+            assert_eq!(Tree::deserialize(&mut reader).unwrap(), without_weights(&tree));
+            assert_eq!(tree.serialized_len(), 10 * tree.leaf_count() as u64 - 1);
+        }
+    }
+
+    #[test]
+    fn canonical_codes_follow_symbol_order_within_each_length() {
+        let tree = tree_of(b"the quick brown fox jumps over the lazy dog");
+        let codes = tree.canonical_encode();
+        assert_eq!(tree.code_lengths(), codes.iter().map(|(&c, &(_, len))| (c, len)).collect());
+
+        let mut sorted: Vec<_> = codes.iter().map(|(&c, &(code, len))| (len, c, code)).collect();
+        sorted.sort();
+        for pair in sorted.windows(2) {
+            let (l_len, _, l_code) = pair[0];
+            let (r_len, _, r_code) = pair[1];
+            // The next code is one more than the last, shifted out to its length.
+            assert_eq!(r_code, (l_code + 1) << (r_len - l_len));
+        }
+        assert_eq!(sorted[0].2, 0);
+    }
+
+    #[test]
+    fn char_trees_cost_the_same_as_byte_trees() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        let mut chars = HashMap::new();
+        for c in text.chars() {
+            *chars.entry(c).or_insert(0u64) += 1;
+        }
+        let chars = Tree::from(chars);
+
+        assert_eq!(chars.weighted_path_length(), tree_of(text.as_bytes()).weighted_path_length());
+        assert_eq!(chars.encode().len(), chars.leaf_count());
+    }
+
+    #[test]
+    fn equal_counts_always_build_the_same_tree() {
+        // Each new `HashMap` is seeded differently, so iterates in a different order.
+        let freqs = || -> HashMap<u8, u64> { (0..40).map(|c| (c, 1 + u64::from(c) % 3)).collect() };
+        for &tiebreak in &[TieBreak::BySymbol, TieBreak::ByInsertion, TieBreak::Minimal] {
+            let first = Tree::from_with_tiebreak(freqs(), tiebreak);
+            for _ in 0..20 {
+                assert_eq!(Tree::from_with_tiebreak(freqs(), tiebreak), first);
+            }
+        }
+
+        let first = Tree::from(freqs());
+        for _ in 0..20 {
+            assert_eq!(Tree::from(freqs()), first);
+        }
+        assert_eq!(Tree::from_with_tiebreak(freqs(), TieBreak::ByInsertion), first);
+    }
+
+    #[test]
+    fn draw_labels_branches_and_leaves() {
+        let tree: Tree = (Leaf(b'A', 42) + Leaf(b'\n', 7)) + Leaf(b'B', 50);
+        assert_eq!(tree.draw(), "\
+(99)
+├─0─ (49)
+│    ├─0─ 'A' (42)
+│    └─1─ 0x0a (7)
+└─1─ 'B' (50)
+");
+    }
+
+    #[test]
+    fn weights_past_u64_max_saturate() {
+        let tree: Tree = Leaf(b'a', u64::MAX - 1) + Leaf(b'b', 2);
+        assert_eq!(tree.prob(), u64::MAX);
+
+        // The saturated root still sorts after everything lighter.
+        let freqs: HashMap<u8, u64> = [(b'a', u64::MAX - 1), (b'b', 2), (b'c', 1)].iter().cloned().collect();
+        let tree = Tree::from(freqs);
+        assert_eq!(tree.prob(), u64::MAX);
+        assert_eq!(tree.code_lengths()[&b'a'], 1);
+    }
+
+    #[test]
+    fn trees_from_code_lengths_decode_canonical_codes() {
+        let input = b"the quick brown fox jumps over the lazy dog";
+        let codes = tree_of(input).canonical_encode();
+        let mut writer = BitWriter::new(Vec::new());
+        encode(input, &codes, &mut writer).unwrap();
+        let data = writer.finish().unwrap();
+
+        let lengths = codes.iter().map(|(&c, &(_, len))| (c, len)).collect();
+        let tree = Tree::from_code_lengths(&lengths).unwrap();
+        assert_eq!(tree.encode(), codes);
+        let mut output = Vec::new();
+        decode(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut output).unwrap();
+        assert_eq!(output, &input[..]);
+    }
+
+    #[test]
+    fn incomplete_or_overfull_lengths_are_rejected() {
+        for lengths in [vec![1, 2], vec![1, 1, 2], vec![2, 2, 2], vec![3]] {
+            let lengths: HashMap<u8, usize> = (0..).zip(lengths).collect();
+            match Tree::from_code_lengths(&lengths) {
+                Err(HuffmanError::IncompletePrefixCode) => {}
+                other => panic!("Expected IncompletePrefixCode for {:?}, got {:?}", lengths, other),
+            }
+        }
+        let lengths: HashMap<u8, usize> = [(0, 1), (1, 0)].iter().cloned().collect();
+        assert!(matches!(Tree::from_code_lengths(&lengths), Err(HuffmanError::InvalidCodeLength(0))));
+    }
+
+    #[test]
+    fn a_complete_code_can_reach_64_bits() {
+        // One code of each length up to 63 bits, then two of 64, the last of which is all ones.
+        let mut lengths: HashMap<u8, usize> = (1..=64).map(|len| (len as u8, len)).collect();
+        lengths.insert(65, 64);
+
+        let codes = canonical_codes(lengths.clone());
+        assert_eq!(codes[&65], (u64::MAX, 64));
+        assert_eq!(codes[&64], (u64::MAX - 1, 64));
+        assert_eq!(Tree::from_code_lengths(&lengths).unwrap().depth(), 64);
+    }
+
+    #[test]
+    fn insertion_order_doesnt_change_the_tree() {
+        let pairs: Vec<(u8, u64)> = (0..30).map(|c| (c, [5, 5, 3, 8][c as usize % 4])).collect();
+        let forwards: HashMap<u8, u64> = pairs.iter().cloned().collect();
+        let backwards: HashMap<u8, u64> = pairs.iter().rev().cloned().collect();
+        assert_eq!(Tree::from(forwards.clone()), Tree::from(backwards));
+
+        let mut shuffled = pairs.clone();
+        shuffled.swap(0, 29);
+        shuffled.swap(3, 17);
+        for &tiebreak in &[TieBreak::BySymbol, TieBreak::ByInsertion, TieBreak::Minimal] {
+            assert_eq!(Tree::from_weights(shuffled.clone(), tiebreak), Tree::from_weights(pairs.clone(), tiebreak));
+        }
+        assert_eq!(Tree::from_weights(shuffled, TieBreak::ByInsertion).code_lengths(), Tree::from(forwards).code_lengths());
+    }
+
+    #[test]
+    fn a_heap_of_trees_pops_the_lightest_first() {
+        let weights = [7u64, 3, 9, 3, 1, 12, 5];
+        let mut heap: BinaryHeap<Tree> = weights.iter().enumerate().map(|(c, &p)| Leaf(c as u8, p)).collect();
+        heap.push(Leaf(7, 2) + Leaf(8, 2));
+
+        let mut popped = Vec::new();
+        while let Some(tree) = heap.pop() {
+            popped.push(tree.prob());
+        }
+        assert_eq!(popped, [1, 3, 3, 4, 5, 7, 9, 12]);
+
+        // Equal weights fall back to structure, so the smaller symbol pops first, and the order
+        // agrees with `PartialOrd` and `Eq`.
+        let (a, b) = (Leaf(b'a', 3), Leaf(b'b', 3));
+        assert_eq!(a.cmp(&b), Ordering::Greater);
+        assert_eq!(a.partial_cmp(&b), Some(a.cmp(&b)));
+        assert_eq!(a.cmp(&a.clone()), Ordering::Equal);
+    }
+
+    #[test]
+    fn byte_labels_quote_only_printable_ascii() {
+        assert_eq!(byte_label(b'x'), "'x'");
+        assert_eq!(byte_label(b' '), "' '");
+        assert_eq!(byte_label(b'\''), "'\\''");
+        for &(c, label) in &[(0x00, "0x00"), (0x0a, "0x0a"), (0x7f, "0x7f"), (0x80, "0x80"), (0xff, "0xff")] {
+            assert_eq!(byte_label(c), label);
+        }
+    }
+
+    #[test]
+    fn the_code_array_agrees_with_the_map() {
+        for input in [&b"z"[..], b"abracadabra", b"the quick brown fox jumps over the lazy dog"] {
+            let tree = tree_of(input);
+            let map = tree.encode();
+            let array = tree.encode_array();
+            for c in 0..=255u8 {
+                match map.get(&c) {
+                    Some(&code) => assert_eq!(array[c as usize], code),
+                    None => assert_eq!(array[c as usize].1, 0),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn codes_iterate_in_order_of_length_then_code() {
+        let tree = tree_of(b"the quick brown fox jumps over the lazy dog");
+        let codes: Vec<_> = tree.iter_codes().collect();
+        assert_eq!(codes.len(), tree.leaf_count());
+        assert!(codes.windows(2).all(|pair| (pair[0].2, pair[0].1) < (pair[1].2, pair[1].1)));
+
+        let map = tree.encode();
+        assert!(codes.iter().all(|&(c, code, depth)| map[&c] == (code, depth)));
+    }
+
+    #[test]
+    fn dot_declares_every_node_and_edge() {
+        let tree = tree_of(b"abracadabra");
+        let dot = tree.to_dot();
+        assert!(dot.starts_with("digraph huffman {\n") && dot.ends_with("}\n"));
+
+        let edges = dot.lines().filter(|line| line.contains(" -> ")).count();
+        let nodes = dot.lines().filter(|line| line.contains(" [label=") && !line.contains(" -> ")).count();
+        let leaves = dot.lines().filter(|line| line.contains("shape=box")).count();
+        assert_eq!((nodes, edges, leaves), (9, 8, 5));
+        assert!(dot.contains("[label=\"'a' (5)\", shape=box]"));
+    }
+
+    #[test]
+    fn depth_and_leaf_count_of_hand_built_trees() {
+        let lone: Tree = Leaf(b'a', 1);
+        assert_eq!((lone.depth(), lone.leaf_count()), (0, 1));
+
+        let pair: Tree = Leaf(b'a', 1) + Leaf(b'b', 1);
+        assert_eq!((pair.depth(), pair.leaf_count()), (1, 2));
+
+        // A vine, one leaf hanging off each level.
+        let vine: Tree = (b'a'..b'f').fold(Leaf(b'f', 1), |tree, c| Leaf(c, 1) + tree);
+        assert_eq!((vine.depth(), vine.leaf_count()), (5, 6));
+
+        let balanced: Tree = (Leaf(b'a', 1) + Leaf(b'b', 1)) + (Leaf(b'c', 1) + Leaf(b'd', 1));
+        assert_eq!((balanced.depth(), balanced.leaf_count()), (2, 4));
+    }
+
+    #[test]
+    fn validate_rejects_repeated_symbols_and_overlong_codes() {
+        assert!(tree_of(b"abracadabra").validate().is_ok());
+
+        let repeated: Tree = Leaf(b'a', 1) + (Leaf(b'b', 1) + Leaf(b'a', 1));
+        assert!(matches!(repeated.validate(), Err(HuffmanError::IncompletePrefixCode)));
+
+        let vine: Tree = (0..65).fold(Leaf(65, 1), |tree, c| Leaf(c, 1) + tree);
+        assert!(matches!(vine.validate(), Err(HuffmanError::InvalidCodeLength(65))));
+        let vine: Tree = (0..64).fold(Leaf(64, 1), |tree, c| Leaf(c, 1) + tree);
+        assert!(vine.validate().is_ok());
+    }
+
+    #[test]
+    fn a_corrupt_serialized_tree_is_rejected() {
+        // A node whose two leaves are both 'a'.
+        let mut writer = BitWriter::new(Vec::new());
+        for &(bits, length) in &[(1, 1), (0, 1), (u64::from(b'a'), 8), (0, 1), (u64::from(b'a'), 8)] {
+            writer.write_bits(bits, length).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        assert!(matches!(Tree::try_from(&bytes[..]), Err(HuffmanError::IncompletePrefixCode)));
+
+        // A node missing its second child.
+        assert!(matches!(Tree::try_from(&bytes[..2]), Err(HuffmanError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn every_tie_break_is_optimal() {
+        // Many ties, where the choice changes the lengths: taking the subtree with the smallest
+        // symbol keeps joining onto the one holding 'a'.
+        let freqs: HashMap<u8, u64> = [(b'a', 1), (b'b', 1), (b'c', 2), (b'd', 2), (b'e', 4), (b'f', 4), (b'g', 8)].iter().cloned().collect();
+        let trees: Vec<Tree> = [TieBreak::BySymbol, TieBreak::ByInsertion, TieBreak::Minimal].iter()
+            .map(|&tiebreak| Tree::from_with_tiebreak(freqs.clone(), tiebreak))
+            .collect();
+
+        let cost = Tree::from(freqs.clone()).weighted_path_length();
+        assert!(trees.iter().all(|tree| tree.weighted_path_length() == cost));
+        assert!(trees.iter().all(|tree| tree.depth() >= trees[2].depth()));
+        assert!(trees[2].depth() < trees[0].depth());
+        assert_eq!(TieBreak::default(), TieBreak::ByInsertion);
+    }
+
+    #[test]
+    fn a_clone_is_equal_and_outlives_the_original() {
+        let tree = tree_of(b"abracadabra");
+        let copy = tree.clone();
+        assert_eq!(copy, tree);
+        assert_ne!(copy, tree_of(b"abracadabrx"));
+
+        // Consuming the original leaves the copy whole.
+        let codes = tree.encode();
+        let joined = tree + Leaf(b'z', 1);
+        assert_eq!(joined.leaf_count(), copy.leaf_count() + 1);
+        assert_eq!(copy.encode(), codes);
+    }
+
+    #[test]
+    fn weighted_path_length_counts_the_coded_bits() {
+        let freqs: HashMap<u8, u64> = [(b'A', 45), (b'B', 13), (b'C', 12), (b'D', 16), (b'E', 9), (b'F', 5)].iter().cloned().collect();
+        let tree = Tree::from(freqs.clone());
+        let codes = tree.encode();
+        let by_hand: u64 = freqs.iter().map(|(c, &count)| count * codes[c].1 as u64).sum();
+        assert_eq!(by_hand, 224);
+        assert_eq!(tree.weighted_path_length(), 224);
+
+        // A lone symbol still takes a bit each.
+        let lone: Tree = Leaf(b'a', 7);
+        assert_eq!(lone.weighted_path_length(), 7);
+
+        // Saturated weights below the root, which no u64 sum could hold.
+        let freqs: HashMap<u8, u64> = [(b'a', u64::MAX), (b'b', u64::MAX), (b'c', u64::MAX)].iter().cloned().collect();
+        assert_eq!(Tree::from(freqs).weighted_path_length(), 5 * u128::from(u64::MAX));
+    }
+
+    #[test]
+    fn a_tree_of_more_than_256_leaves_is_rejected() {
+        // 256 nodes down the left, each of which would need a leaf to its right.
+        let mut writer = BitWriter::new(Vec::new());
+        for _ in 0..256 {
+            writer.write_bits(1, 1).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        match Tree::deserialize(&mut BitReader::new(&bytes[..])) {
+            Err(HuffmanError::AlphabetTooLarge(256)) => {}
+            other => panic!("Expected AlphabetTooLarge, got {:?}", other),
+        }
+
+        // 255 nodes are as many as every byte takes.
+        let tree = tree_of(&(0..=255).collect::<Vec<u8>>());
+        let mut writer = BitWriter::new(Vec::new());
+        tree.serialize(&mut writer).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_eq!(Tree::deserialize(&mut BitReader::new(&bytes[..])).unwrap().leaf_count(), 256);
+    }
+
+    #[test]
+    fn code_lengths_meet_the_kraft_equality() {
+        let mut random = Xorshift::new(76);
+        let inputs = [random.bytes(2000, 256), random.bytes(2000, 3), random.skewed_bytes(5000), b"ab".to_vec()];
+        for input in inputs.iter() {
+            let tree = tree_of(input);
+            let lengths = tree.code_lengths();
+            let codes = tree.encode();
+            assert_eq!(lengths.len(), codes.len());
+            assert!(lengths.iter().all(|(c, &len)| codes[c].1 == len));
+
+            // Sum 2^-length in units of 2^-depth, so that it is exact.
+            let depth = tree.depth();
+            let kraft: u128 = lengths.values().map(|&len| 1u128 << (depth - len)).sum();
+            assert_eq!(kraft, 1u128 << depth);
+        }
+
+        // A lone symbol still takes a bit, and so leaves half the codes unused.
+        let lone: Tree = Leaf(b'a', 3);
+        assert_eq!(lone.code_lengths()[&b'a'], 1);
+    }
+
+    #[test]
+    fn a_hundred_builds_of_the_same_input_agree() {
+        // Every byte the same number of times, so that every join is a tie.
+        let input: Vec<u8> = (0..=255).cycle().take(256 * 4).collect();
+        let first = tree_of(&input);
+        for _ in 0..100 {
+            assert_eq!(tree_of(&input), first);
+        }
+        assert_eq!(first.depth(), 8);
+    }
+
+    /// A probability, ordered as `f64::total_cmp` orders them.
+    #[derive(Debug, Clone, Copy)]
+    struct Probability(f64);
+
+    impl Add for Probability {
+        type Output = Probability;
+
+        fn add(self, other: Probability) -> Probability {
+            Probability(self.0 + other.0)
+        }
+    }
+
+    impl Ord for Probability {
+        fn cmp(&self, other: &Probability) -> Ordering {
+            self.0.total_cmp(&other.0)
+        }
+    }
+
+    impl PartialOrd for Probability {
+        fn partial_cmp(&self, other: &Probability) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl PartialEq for Probability {
+        fn eq(&self, other: &Probability) -> bool {
+            self.cmp(other) == Ordering::Equal
+        }
+    }
+
+    impl Eq for Probability {}
+
+    impl Weight for Probability {}
+
+    #[test]
+    fn trees_can_be_built_from_probabilities() {
+        let probabilities = [(b'A', 0.45), (b'B', 0.13), (b'C', 0.12), (b'D', 0.16), (b'E', 0.09), (b'F', 0.05)];
+        let tree = Tree::from_weights(probabilities.iter().map(|&(c, p)| (c, Probability(p))), TieBreak::BySymbol);
+        assert!((tree.prob().0 - 1.0).abs() < 1e-12);
+
+        let lengths = tree.code_lengths();
+        let expected: HashMap<u8, usize> = [(b'A', 1), (b'B', 3), (b'C', 3), (b'D', 3), (b'E', 4), (b'F', 4)].iter().cloned().collect();
+        assert_eq!(lengths, expected);
+
+        // The same shape as counts in the same proportions build.
+        let counts: HashMap<u8, u64> = probabilities.iter().map(|&(c, p)| (c, (p * 100.0).round() as u64)).collect();
+        assert_eq!(Tree::from(counts).symbols().collect::<Vec<_>>(), tree.symbols().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn symbols_come_out_in_pre_order() {
+        fn pre_order(tree: &Tree, symbols: &mut Vec<u8>) {
+            match tree {
+                Leaf(c, _) => symbols.push(*c),
+                Node(l, r, _) => {
+                    pre_order(l, symbols);
+                    pre_order(r, symbols);
+                }
+            }
+        }
+
+        for input in &[&b"a"[..], b"abracadabra", b"the quick brown fox jumps over the lazy dog"] {
+            let tree = tree_of(input);
+            let mut walked = Vec::new();
+            pre_order(&tree, &mut walked);
+            assert_eq!(tree.symbols().collect::<Vec<_>>(), walked);
+        }
+
+        // Left to right is in order of code, with each code read as a fraction in [0, 1).
+        let tree: Tree = ((Leaf(b'a', 1) + Leaf(b'b', 1)) + Leaf(b'c', 2)) + Leaf(b'd', 4);
+        assert_eq!(tree.symbols().collect::<Vec<_>>(), b"abcd");
+        let codes = tree.encode();
+        let aligned = |c: u8| codes[&c].0 << (64 - codes[&c].1);
+        let symbols: Vec<u8> = tree.symbols().collect();
+        assert!(symbols.windows(2).all(|pair| aligned(pair[0]) < aligned(pair[1])));
+    }
+
+    #[test]
+    fn the_rarest_symbol_is_deepest() {
+        let freqs: HashMap<u8, u64> = [(b'a', 64), (b'b', 32), (b'c', 16), (b'd', 8), (b'e', 4), (b'f', 1)].iter().cloned().collect();
+        let tree = Tree::from(freqs);
+        assert_eq!(tree.depth(), 5);
+        assert_eq!(tree.deepest_symbols(), [&b'f', &b'e']);
+
+        let lone: Tree = Leaf(b'a', 1);
+        assert_eq!(lone.deepest_symbols(), [&b'a']);
+    }
+
+    #[test]
+    fn the_length_histogram_counts_every_code() {
+        let mut random = Xorshift::new(92);
+        let inputs = [random.bytes(3000, 256), random.bytes(100, 6), random.skewed_bytes(5000), b"abracadabra".to_vec()];
+        for input in inputs.iter() {
+            let tree = tree_of(input);
+            let histogram = tree.length_histogram();
+            assert_eq!(histogram.len(), tree.depth() + 1);
+            assert_eq!(histogram[0], 0);
+            assert_eq!(histogram.iter().sum::<u64>(), tree.leaf_count() as u64);
+
+            // Sum 2^-length in units of 2^-depth, so that it is exact.
+            let depth = histogram.len() - 1;
+            let kraft: u128 = histogram.iter().enumerate().map(|(len, &count)| u128::from(count) << (depth - len)).sum();
+            assert_eq!(kraft, 1u128 << depth);
+        }
+
+        let lone: Tree = Leaf(b'a', 1);
+        assert_eq!(lone.length_histogram(), [0, 1]);
+    }
+
+    #[test]
+    fn equal_counts_get_balanced_codes() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        for &tiebreak in &[TieBreak::BySymbol, TieBreak::ByInsertion, TieBreak::Minimal] {
+            let tree = Tree::from_with_tiebreak(count_frequencies(&all_bytes[..]).unwrap(), tiebreak);
+            let lengths = tree.code_lengths();
+            assert_eq!(lengths.len(), 256);
+            assert!(lengths.values().all(|&len| len == 8), "{:?}", tiebreak);
+        }
+
+        // Any number of symbols gets codes of floor(log2(n)) or ceil(log2(n)) bits.
+        for n in 2..=100u8 {
+            let tree = tree_of(&(0..n).collect::<Vec<u8>>());
+            let (shortest, longest) = (tree.code_lengths().values().cloned().min().unwrap(), tree.depth());
+            assert!(longest - shortest <= 1, "{} symbols", n);
+            assert_eq!(longest, (usize::from(n) - 1).ilog2() as usize + 1);
+        }
+    }
+
+    #[test]
+    fn trees_parse_from_their_serialized_bytes() {
+        let tree = tree_of(b"the quick brown fox jumps over the lazy dog");
+        let mut writer = BitWriter::new(Vec::new());
+        tree.serialize(&mut writer).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_eq!(Tree::try_from(&bytes[..]).unwrap(), without_weights(&tree));
+
+        for len in 0..bytes.len() {
+            match Tree::try_from(&bytes[..len]) {
+                Err(HuffmanError::UnexpectedEof) => {}
+                other => panic!("Expected UnexpectedEof from {} bytes, got {:?}", len, other),
+            }
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        match Tree::try_from(&trailing[..]) {
+            Err(HuffmanError::InvalidHeader(message)) => assert_eq!(message, "Data follows the serialized tree"),
+            other => panic!("Expected InvalidHeader, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sorted_weights_build_the_same_tree_as_the_heap() {
+        let mut random = Xorshift::new(100);
+        let inputs = [random.bytes(5000, 256), random.bytes(500, 40), random.skewed_bytes(5000), b"abracadabra".to_vec(), b"a".to_vec()];
+        for input in inputs.iter() {
+            let freqs = count_frequencies(&input[..]).unwrap();
+            let mut sorted: Vec<(u8, u64)> = freqs.iter().map(|(&c, &count)| (c, count)).collect();
+            sorted.sort_by_key(|&(c, count)| (count, c));
+
+            let heap = Tree::from_weights(freqs.clone(), TieBreak::ByInsertion);
+            let queues = Tree::from_sorted(sorted);
+            assert_eq!(queues.code_lengths(), heap.code_lengths());
+            assert_eq!(queues, heap);
+            // Whichever of them it uses.
+            assert_eq!(Tree::from(freqs), heap);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Weights must be sorted")]
+    fn unsorted_weights_are_a_bug() {
+        Tree::from_sorted(vec![(b'a', 2u64), (b'b', 1)]);
+    }
+}
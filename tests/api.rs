@@ -0,0 +1,27 @@
+//! Uses the library as a downstream crate would, through its public API alone.
+
+extern crate rust_huffman;
+
+use std::collections::HashMap;
+
+use rust_huffman::{BitReader, BitWriter, build_tree, decode, encode, encoding_table};
+
+#[test]
+fn build_encode_and_decode_through_the_public_api() {
+    let input = b"she sells sea shells by the sea shore";
+    let mut freqs = HashMap::new();
+    for &c in input.iter() {
+        *freqs.entry(c).or_insert(0) += 1;
+    }
+
+    let tree = build_tree(freqs).expect("The input isn't empty");
+    let table = encoding_table(&tree);
+    let mut writer = BitWriter::new(Vec::new());
+    encode(input, &table, &mut writer).unwrap();
+    let data = writer.finish().unwrap();
+    assert!(data.len() < input.len());
+
+    let mut output = Vec::new();
+    decode(&mut BitReader::new(&data[..]), &tree, input.len() as u64, &mut output).unwrap();
+    assert_eq!(output, &input[..]);
+}
@@ -0,0 +1,500 @@
+//! Runs the `rust-huffman` binary as a user would.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Run the binary with `args`, feeding it `input` on stdin. It may exit without reading all of it,
+/// such as on an error, which leaves the rest unwritten.
+fn run(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-huffman"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("The binary runs");
+    if let Err(e) = child.stdin.take().unwrap().write_all(input) {
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// An empty directory of its own for the test called `name`.
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rust-huffman-cli-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The path of a file in `tests/fixtures`.
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Some text with the skew in its bytes that English has.
+fn redundant_text() -> Vec<u8> {
+    b"the quick brown fox jumps over the lazy dog, and then the dog sleeps. ".repeat(50)
+}
+
+/// `len` bytes from xorshift64, which are as good as incompressible.
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn encode_shrinks_redundant_input() {
+    let input = redundant_text();
+    let output = run(&["--encode"], &input);
+    assert!(output.status.success());
+    assert!(!output.stdout.is_empty());
+    assert!(output.stdout.len() < input.len(), "{} bytes grew to {}", input.len(), output.stdout.len());
+}
+
+#[test]
+fn empty_input_prints_an_empty_table() {
+    let output = run(&[], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Encoding\n========\n");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn input_and_output_files_round_trip() {
+    let dir = scratch("files");
+    let (original, compressed, decompressed) = (dir.join("original"), dir.join("compressed"), dir.join("decompressed"));
+    fs::write(&original, redundant_text()).unwrap();
+
+    let output = run(&["--encode", "-i", original.to_str().unwrap(), "-o", compressed.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let output = run(&["--decode", "--input", compressed.to_str().unwrap(), "--output", decompressed.to_str().unwrap()], b"");
+    assert!(output.status.success());
+
+    assert_eq!(fs::read(&decompressed).unwrap(), redundant_text());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_missing_input_is_reported_with_its_path() {
+    let dir = scratch("missing");
+    let missing = dir.join("missing");
+    let output = run(&["--encode", "-i", missing.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains(missing.to_str().unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_table_reports_entropy_and_efficiency() {
+    let output = run(&["--table"], b"abcd");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("entropy:             2.0000 bits/symbol\n"), "{}", stdout);
+    assert!(stdout.contains("average code length: 2.0000 bits/symbol\n"), "{}", stdout);
+    assert!(stdout.contains("efficiency:          100.00%\n"), "{}", stdout);
+}
+
+#[test]
+fn the_cost_report_ends_with_the_predicted_size() {
+    let output = run(&["--freq", "A=45,B=13,C=12,D=16,E=9,F=5"], b"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let report = &stdout[stdout.find("Cost\n").expect("A cost report")..];
+    assert_eq!(report, "\
+Cost
+====
+symbol   probability length          bytes
+'D'         0.160000      3          6.000
+'A'         0.450000      1          5.625
+'B'         0.130000      3          4.875
+'C'         0.120000      3          4.500
+'E'         0.090000      4          4.500
+'F'         0.050000      4          2.500
+
+predicted compressed size: 28 bytes
+");
+}
+
+#[test]
+fn verify_passes_on_a_binary_fixture() {
+    let output = run(&["--verify", "-i", &fixture("sample.bin")], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "OK: 3728 bytes compressed to 2497 (ratio 0.670)\n");
+}
+
+#[test]
+fn a_table_file_codes_and_decodes() {
+    let dir = scratch("table-file");
+    let table = dir.join("table");
+    let mut lines = "# trained on lowercase text\n0x20 10\n".to_string();
+    for c in b'a'..=b'z' {
+        lines.push_str(&format!("{} {}\n", c, 1 + c % 5));
+    }
+    fs::write(&table, lines).unwrap();
+    let table = table.to_str().unwrap();
+
+    let input = b"the quick brown fox jumps over the lazy dog";
+    let encoded = run(&["--encode", "--table-file", table], input);
+    assert!(encoded.status.success());
+    let decoded = run(&["--decode", "--table-file", table], &encoded.stdout);
+    assert!(decoded.status.success());
+    assert_eq!(decoded.stdout, &input[..]);
+
+    fs::write(dir.join("bad"), "0x20 10\nspace 3\n").unwrap();
+    let output = run(&["--encode", "--table-file", dir.join("bad").to_str().unwrap()], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("line 2"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn analyze_summarises_a_fixture() {
+    let output = run(&["--analyze", "-i", &fixture("sample.bin")], b"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("distinct symbols: 249\n"), "{}", stdout);
+    assert!(stdout.contains("entropy:          4.5896 bits/symbol\n"), "{}", stdout);
+}
+
+#[test]
+fn the_table_renders_bytes_that_arent_ascii() {
+    let output = run(&[], b"\x00\x0a\x80\xff\x0a");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("\
+Encoding
+========
+<0x00> => 0b00
+<0x80> => 0b01
+<0xFF> => 0b10
+<0x0A> => 0b11
+"), "{}", stdout);
+}
+
+/// The symbols listed in a table printed by `--table`.
+fn table_keys(stdout: &str) -> Vec<&str> {
+    stdout.lines()
+        .skip(2)
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.split(" => ").next().unwrap())
+        .collect()
+}
+
+#[test]
+fn words_are_coded_little_endian() {
+    let samples: Vec<u8> = [1u16, 1, 2, 0x100, 1].iter().flat_map(|w| w.to_le_bytes()).collect();
+    let output = run(&["--word-size", "2"], &samples);
+    assert!(output.status.success());
+    assert_eq!(table_keys(&String::from_utf8(output.stdout).unwrap()), ["0x0001", "0x0002", "0x0100"]);
+
+    let samples: Vec<u8> = [7u32, 0x1234_5678].iter().flat_map(|w| w.to_le_bytes()).collect();
+    let output = run(&["--word-size", "4"], &samples);
+    assert!(output.status.success());
+    assert_eq!(table_keys(&String::from_utf8(output.stdout).unwrap()), ["0x00000007", "0x12345678"]);
+}
+
+#[test]
+fn a_partial_word_is_an_error() {
+    let output = run(&["--word-size", "4"], b"\x01\x00\x00\x00\x02\x00");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "rust-huffman: input ends with a partial 4-byte word at byte 4\n");
+}
+
+#[test]
+fn dot_prints_the_tree() {
+    let output = run(&["--dot", "--freq", "A=3,B=1,C=1"], b"");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "\
+digraph huffman {
+    n0 [label=\"5\"];
+    n1 [label=\"2\"];
+    n2 [label=\"'B' (1)\", shape=box];
+    n1 -> n2 [label=\"0\"];
+    n3 [label=\"'C' (1)\", shape=box];
+    n1 -> n3 [label=\"1\"];
+    n0 -> n1 [label=\"0\"];
+    n4 [label=\"'A' (3)\", shape=box];
+    n0 -> n4 [label=\"1\"];
+}
+");
+}
+
+#[test]
+fn rle_round_trips_through_the_cli() {
+    let input = [vec![b'x'; 5000], vec![b'y'; 3000], vec![b'x'; 2000]].concat();
+    let plain = run(&["--encode"], &input);
+    let rle = run(&["--encode", "--rle"], &input);
+    assert!(rle.status.success());
+    assert!(rle.stdout.len() < plain.stdout.len());
+
+    let decoded = run(&["--decode"], &rle.stdout);
+    assert!(decoded.status.success());
+    assert_eq!(decoded.stdout, input);
+}
+
+#[test]
+fn blocks_round_trip_through_the_cli() {
+    let input = [b"abacabad".repeat(500), b"wxyzzyxz".repeat(500)].concat();
+    let encoded = run(&["--encode", "--block-size", "1000"], &input);
+    assert!(encoded.status.success());
+    assert_eq!(encoded.stdout.windows(4).filter(|w| w == b"HUF1").count(), 8);
+
+    let decoded = run(&["--decode"], &encoded.stdout);
+    assert!(decoded.status.success());
+    assert_eq!(decoded.stdout, input);
+}
+
+/// The fields of a JSON object whose values are all numbers or booleans.
+fn parse_flat_json(json: &str) -> HashMap<String, String> {
+    let body = json.trim().strip_prefix('{').and_then(|json| json.strip_suffix('}')).expect("A JSON object");
+    body.split(',')
+        .map(|field| {
+            let (key, value) = field.split_once(':').expect("A key and value");
+            let key = key.trim().strip_prefix('"').and_then(|key| key.strip_suffix('"')).expect("A quoted key");
+            (key.to_string(), value.trim().to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn stats_json_describes_the_compression() {
+    let dir = scratch("stats-json");
+    let compressed = dir.join("compressed");
+    let output = run(&["--encode", "--stats-json", "-i", &fixture("sample.bin"), "-o", compressed.to_str().unwrap()], b"");
+    assert!(output.status.success());
+
+    let stats = parse_flat_json(&String::from_utf8(output.stdout).unwrap());
+    let field = |name: &str| stats.get(name).unwrap_or_else(|| panic!("No field {}", name)).parse::<f64>().unwrap();
+    let output_size = fs::metadata(&compressed).unwrap().len() as f64;
+    assert_eq!(field("input_size"), 3728.0);
+    assert_eq!(field("output_size"), output_size);
+    assert!((field("ratio") - output_size / 3728.0).abs() < 1e-9);
+    assert!((field("entropy") - 4.5896).abs() < 1e-4);
+    assert_eq!(field("distinct_symbols"), 249.0);
+    assert!(field("max_code_depth") >= 8.0);
+    assert_eq!(stats["incompressible"], "false");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn encode_pipes_into_decode() {
+    let input = redundant_text();
+    let binary = env!("CARGO_BIN_EXE_rust-huffman");
+    let mut encoder = Command::new(binary).arg("--encode").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    let decoder = Command::new(binary)
+        .arg("--decode")
+        .stdin(encoder.stdout.take().unwrap())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    encoder.stdin.take().unwrap().write_all(&input).unwrap();
+
+    assert!(encoder.wait().unwrap().success());
+    let output = decoder.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert_eq!(output.stdout, input);
+}
+
+#[test]
+fn a_closed_output_pipe_ends_decoding_quietly() {
+    let encoded = run(&["--encode"], &redundant_text().repeat(20)).stdout;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-huffman"))
+        .arg("--decode")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Stop reading before anything is decoded, as `head` does once it has seen enough.
+    drop(child.stdout.take());
+    // The decoder may stop reading its input once the output has gone.
+    let _ = child.stdin.take().unwrap().write_all(&encoded);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn freq_gives_the_textbook_code_lengths() {
+    let output = run(&["--freq", "A=45,B=13,C=12,D=16,E=9,F=5"], b"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lengths: Vec<(&str, usize)> = stdout.lines()
+        .skip(2)
+        .take_while(|line| !line.is_empty())
+        .map(|line| {
+            let mut parts = line.split(" => ");
+            (parts.next().unwrap().trim(), parts.next().unwrap().len() - "0b".len())
+        })
+        .collect();
+    lengths.sort();
+    assert_eq!(lengths, [("'A'", 1), ("'B'", 3), ("'C'", 3), ("'D'", 3), ("'E'", 4), ("'F'", 4)]);
+
+    let output = run(&["--freq", "A=5,B"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("rust-huffman: --freq: expected '<symbol>=<count>', found 'B'\n"));
+}
+
+#[test]
+fn freq_takes_counts_up_to_u64_max() {
+    let output = run(&["--freq", "A=18446744073709551615,B=18446744073709551615"], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("predicted compressed size: 4611686018427387904 bytes\n"), "{}", stdout);
+
+    let output = run(&["--analyze", "--freq", "A=18446744073709551615,B=18446744073709551615,C=1"], b"");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn random_input_is_warned_about() {
+    let warning = "rust-huffman: warning: the input's entropy is";
+    let output = run(&["--encode"], &random_bytes(1 << 16));
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with(warning));
+
+    // Random bytes fall just short of 8 bits each.
+    let output = run(&["--encode", "--warn-entropy", "8"], &random_bytes(1 << 16));
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let output = run(&["--encode", "--warn-entropy", "8.5"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().starts_with("rust-huffman: --warn-entropy must be a number of bits from 0 to 8\n"));
+    let output = run(&["--encode"], &redundant_text());
+    assert!(output.stderr.is_empty());
+
+    let dir = scratch("incompressible");
+    let (original, compressed) = (dir.join("original"), dir.join("compressed"));
+    fs::write(&original, random_bytes(1 << 16)).unwrap();
+    let output = run(&["--encode", "--stats-json", "-i", original.to_str().unwrap(), "-o", compressed.to_str().unwrap()], b"");
+    assert!(output.status.success());
+    assert_eq!(parse_flat_json(&String::from_utf8(output.stdout).unwrap())["incompressible"], "true");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn headerless_data_decodes_with_its_codebook() {
+    let dir = scratch("codebook");
+    let codebook = dir.join("codebook");
+    let codebook = codebook.to_str().unwrap();
+    let input = redundant_text();
+    let encoded = run(&["--encode", "--codebook", codebook], &input);
+    assert!(encoded.status.success());
+    assert!(!encoded.stdout.starts_with(b"HUF"));
+    assert!(fs::read(codebook).unwrap().starts_with(b"HUFC"));
+
+    let decoded = run(&["--decode", "--codebook", codebook], &encoded.stdout);
+    assert!(decoded.status.success());
+    assert_eq!(decoded.stdout, input);
+
+    let output = run(&["--decode"], &encoded.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "rust-huffman: input has no header; if it was encoded with --table-file or --codebook, decode it with the same option\n"
+    );
+
+    let not_a_codebook = fixture("sample.bin");
+    let output = run(&["--decode", "--codebook", &not_a_codebook], &encoded.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Expected codebook magic \"HUFC\""));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_table_names_the_symbols_with_the_longest_code() {
+    let output = run(&["--freq", "a=64,b=32,c=16,d=8,e=4,f=1"], b"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\nlongest code:        5 bits, for 'f', 'e'\n"), "{}", stdout);
+}
+
+#[test]
+fn raw_bits_prints_each_code_in_turn() {
+    // 'a' is the heavier, so takes the right branch, 1, and 'b' the left, 0.
+    let output = run(&["--encode", "--raw-bits"], b"aab");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "11000000\n");
+
+    // A tie puts the lower symbol on the left.
+    let output = run(&["--encode", "--raw-bits"], b"abababababab");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "01010101 01010000\n");
+}
+
+#[test]
+fn keep_name_recreates_the_file_by_name() {
+    let dir = scratch("keep-name");
+    let (original, compressed, restored) = (dir.join("foo.txt"), dir.join("compressed"), dir.join("restored"));
+    fs::write(&original, redundant_text()).unwrap();
+    fs::create_dir(&restored).unwrap();
+    let output = run(&["--encode", "--keep-name", "-i", original.to_str().unwrap(), "-o", compressed.to_str().unwrap()], b"");
+    assert!(output.status.success());
+
+    let decode = || {
+        Command::new(env!("CARGO_BIN_EXE_rust-huffman"))
+            .args(["--decode", "--keep-name", "-i", compressed.to_str().unwrap()])
+            .current_dir(&restored)
+            .output()
+            .unwrap()
+    };
+    let output = decode();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(restored.join("foo.txt")).unwrap(), redundant_text());
+
+    // An existing file is left alone.
+    let output = decode();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("foo.txt"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unprintable_bytes_are_marked_with_a_legend() {
+    let output = run(&[], b"\x1b\x1b\x7f\x00\x80ab\t");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "rust-huffman: warning: the input has bytes that aren't printable ASCII, marked in the table\n"
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let keys: Vec<&str> = table_keys(&stdout).iter().map(|key| key.trim_end()).collect();
+    assert_eq!(keys, ["<0x1B>", "<0x00>", "<0x09>", "'a'", "'b'", "<DEL>", "<0x80>"]);
+    assert!(stdout.contains(
+        "\n\nnote: <0x00> to <0x1F> are control bytes; <DEL> is the delete byte 0x7f; <0x80> to <0xFF> are bytes above ASCII\n\n"
+    ), "{}", stdout);
+
+    let output = run(&[], b"plain text");
+    assert!(output.stderr.is_empty());
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("note:"));
+}
+
+#[test]
+fn piped_input_is_spooled_past_the_threshold() {
+    let input = redundant_text();
+    let whole = run(&["--encode"], &input);
+    assert!(whole.status.success());
+    let spooled = run(&["--encode", "--spool-above", "100"], &input);
+    assert!(spooled.status.success());
+    assert_eq!(spooled.stdout, whole.stdout);
+
+    let decoded = run(&["--decode"], &spooled.stdout);
+    assert!(decoded.status.success());
+    assert_eq!(decoded.stdout, input);
+}
@@ -0,0 +1,82 @@
+//! Compresses and decompresses files on disk.
+
+extern crate rust_huffman;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_huffman::{HuffmanError, compress_file, decompress_file};
+
+/// An empty directory of its own for the test called `name`.
+fn scratch(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rust-huffman-file-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Compress and decompress the file at `path`, checking the stats and that it comes back the same.
+fn assert_round_trips(path: &Path, dir: &Path) {
+    let (compressed, decompressed) = (dir.join("compressed"), dir.join("decompressed"));
+    let original = fs::read(path).unwrap();
+
+    let stats = compress_file(path, &compressed).unwrap();
+    assert_eq!(stats.input_size, original.len() as u64);
+    assert_eq!(stats.output_size, fs::metadata(&compressed).unwrap().len());
+
+    let stats = decompress_file(&compressed, &decompressed).unwrap();
+    assert_eq!(stats.input_size, fs::metadata(&compressed).unwrap().len());
+    assert_eq!(stats.output_size, original.len() as u64);
+    assert_eq!(fs::read(&decompressed).unwrap(), original);
+}
+
+#[test]
+fn source_files_round_trip() {
+    let dir = scratch("source");
+    for name in &["src/lib.rs", "src/tree.rs", "tests/fixtures/sample.bin"] {
+        assert_round_trips(&Path::new(env!("CARGO_MANIFEST_DIR")).join(name), &dir);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn text_shrinks() {
+    let dir = scratch("text");
+    let stats = compress_file(Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tree.rs"), dir.join("compressed")).unwrap();
+    assert!(!stats.stored);
+    assert!(stats.ratio() < 0.75, "Compressed to {}", stats.ratio());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_same_input_compresses_to_the_same_bytes() {
+    let dir = scratch("repeat");
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.bin");
+    compress_file(&path, dir.join("first")).unwrap();
+    compress_file(&path, dir.join("second")).unwrap();
+    assert_eq!(fs::read(dir.join("first")).unwrap(), fs::read(dir.join("second")).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn decompressing_checks_the_magic_and_the_symbol_count() {
+    let dir = scratch("invalid");
+    let (compressed, decompressed) = (dir.join("compressed"), dir.join("decompressed"));
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tree.rs");
+    match decompress_file(&source, &decompressed) {
+        Err(HuffmanError::InvalidHeader(message)) => assert!(message.starts_with("Expected magic"), "{}", message),
+        other => panic!("Expected InvalidHeader, got {:?}", other),
+    }
+
+    // Fewer bits than the symbol count says were coded.
+    compress_file(&source, &compressed).unwrap();
+    let mut data = fs::read(&compressed).unwrap();
+    data.truncate(data.len() - 16);
+    fs::write(&compressed, data).unwrap();
+    match decompress_file(&compressed, &decompressed) {
+        Err(HuffmanError::UnexpectedEof) => {}
+        other => panic!("Expected UnexpectedEof, got {:?}", other),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,45 @@
+//! Runs the decoder over the fuzz target's seeds and damaged streams, as `cargo fuzz` would, so
+//! that regressions show up without it.
+
+extern crate rust_huffman;
+
+use std::fs;
+use std::io::sink;
+use std::path::Path;
+
+use rust_huffman::{compress, read_blocks, read_codebook};
+
+/// Decode `data` as the fuzz target does. Any panic fails the test.
+fn decode(data: &[u8]) -> bool {
+    let decoded = read_blocks(data, sink()).is_ok();
+    let _ = read_codebook(data);
+    decoded
+}
+
+#[test]
+fn the_seeds_are_errors() {
+    let seeds = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/seeds/decode");
+    let mut count = 0;
+    for entry in fs::read_dir(seeds).unwrap() {
+        let path = entry.unwrap().path();
+        assert!(!decode(&fs::read(&path).unwrap()), "{} decoded", path.display());
+        count += 1;
+    }
+    assert!(count >= 2);
+}
+
+#[test]
+fn damaged_streams_never_panic() {
+    let original = compress(&b"the quick brown fox jumps over the lazy dog. ".repeat(20));
+    assert!(decode(&original));
+    for len in 0..original.len() {
+        assert!(!decode(&original[..len]), "{} bytes decoded", len);
+    }
+
+    let mut damaged = original.clone();
+    for i in 0..damaged.len() * 8 {
+        damaged[i / 8] ^= 1 << (i % 8);
+        decode(&damaged);
+        damaged[i / 8] ^= 1 << (i % 8);
+    }
+}